@@ -1,16 +1,302 @@
+use regex::bytes::Regex;
+use std::num::{NonZeroU64, NonZeroUsize};
 use std::path::PathBuf;
+use std::str::FromStr;
 use structopt::StructOpt;
 
+use encoding_rs::Encoding;
+
+use crate::count::{Counts, Strategy};
+use crate::decode::parse_encoding;
+use crate::output::{SortKey, Template};
+
+/// Parse a single byte, given either literally or as one of the escapes \0, \t, \n,
+/// \r, \\ or \xNN
+fn parse_byte(s: &str) -> Result<u8, String> {
+    let byte = match s.as_bytes() {
+        [b] if b.is_ascii() => Some(*b),
+        [b'\\', b'0'] => Some(b'\0'),
+        [b'\\', b't'] => Some(b'\t'),
+        [b'\\', b'n'] => Some(b'\n'),
+        [b'\\', b'r'] => Some(b'\r'),
+        [b'\\', b'\\'] => Some(b'\\'),
+        [b'\\', b'x', hex @ ..] if hex.len() == 2 => u8::from_str_radix(&s[2..], 16).ok(),
+        _ => None,
+    };
+
+    byte.ok_or_else(|| format!("invalid byte {:?}", s))
+}
+
+fn parse_line_delimiter(s: &str) -> Result<u8, String> {
+    match parse_byte(s)? {
+        b if b.is_ascii() => Ok(b),
+        _ => Err(format!("line delimiter {:?} is not ASCII", s)),
+    }
+}
+
+/// A set of bytes, such as the word separators given to `--word-separators`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ByteSet([bool; 256]);
+
+impl ByteSet {
+    /// The ASCII whitespace bytes: space, \t, \n, \x0b, \x0c and \r
+    pub fn ascii_whitespace() -> Self {
+        let mut set = [false; 256];
+        for b in b" \t\n\x0b\x0c\r" {
+            set[*b as usize] = true;
+        }
+        ByteSet(set)
+    }
+
+    #[inline]
+    pub fn contains(&self, b: u8) -> bool {
+        self.0[b as usize]
+    }
+}
+
+impl FromStr for ByteSet {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut set = [false; 256];
+        let mut rest = s;
+
+        while !rest.is_empty() {
+            let len = match rest.as_bytes() {
+                [b'\\', b'x', ..] => 4,
+                [b'\\', ..] => 2,
+                _ => rest.chars().next().map_or(1, char::len_utf8),
+            };
+            let byte = rest.get(..len).unwrap_or(rest);
+            set[parse_byte(byte)? as usize] = true;
+            rest = &rest[byte.len()..];
+        }
+
+        Ok(ByteSet(set))
+    }
+}
+
+/// Parse a non-zero size in bytes, with an optional K, M or G suffix for powers of 1024
+fn parse_size(s: &str) -> Result<NonZeroUsize, String> {
+    let (digits, scale) = match s.char_indices().last() {
+        Some((i, 'K')) | Some((i, 'k')) => (&s[..i], 1 << 10),
+        Some((i, 'M')) | Some((i, 'm')) => (&s[..i], 1 << 20),
+        Some((i, 'G')) | Some((i, 'g')) => (&s[..i], 1 << 30),
+        _ => (s, 1),
+    };
+
+    digits
+        .parse::<usize>()
+        .ok()
+        .and_then(|n| n.checked_mul(scale))
+        .and_then(NonZeroUsize::new)
+        .ok_or_else(|| format!("invalid size {}", s))
+}
+
+/// A range of byte offsets, as given to `--bytes-range`, such as `0..4096`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteRange {
+    pub start: u64,
+    /// The offset after the last byte, or `None` to read to the end
+    pub end: Option<u64>,
+}
+
+impl ByteRange {
+    /// The most bytes the range can cover
+    pub fn limit(&self) -> u64 {
+        self.end.map_or(u64::MAX, |end| end - self.start)
+    }
+}
+
+impl FromStr for ByteRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid byte range {}, expected START..END", s);
+        let offset = |n: &str| n.parse::<u64>().map_err(|_| invalid());
+
+        let (start, end) = s.split_once("..").ok_or_else(invalid)?;
+        let start = if start.is_empty() { 0 } else { offset(start)? };
+        let end = if end.is_empty() {
+            None
+        } else {
+            Some(offset(end)?)
+        };
+
+        match end {
+            Some(end) if end < start => Err(invalid()),
+            end => Ok(ByteRange { start, end }),
+        }
+    }
+}
+
+/// A largest allowed value of a metric, as given to `--limit`, such as `lines=500`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limit {
+    pub metric: &'static str,
+    pub max: u64,
+}
+
+impl FromStr for Limit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, max) = s
+            .split_once('=')
+            .ok_or_else(|| format!("invalid limit {}, expected METRIC=N", s))?;
+
+        let metric = Counts::default()
+            .metrics(&Opt::default())
+            .into_iter()
+            .map(|(metric, _, _)| metric)
+            .find(|metric| *metric == name)
+            .ok_or_else(|| format!("unknown metric {}", name))?;
+
+        let max = max
+            .parse()
+            .map_err(|_| format!("invalid limit {} for {}", max, metric))?;
+
+        Ok(Limit { metric, max })
+    }
+}
+
+/// When to print the total of all inputs
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Total {
+    /// Only when there's more than one input
+    #[default]
+    Auto,
+    Always,
+    Never,
+    /// Print only the total, without a label
+    Only,
+}
+
+impl FromStr for Total {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(Total::Auto),
+            "always" => Ok(Total::Always),
+            "never" => Ok(Total::Never),
+            "only" => Ok(Total::Only),
+            _ => Err(format!("unknown total mode {}", s)),
+        }
+    }
+}
+
+/// Whether to color columnar output
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ColorChoice {
+    /// When printing to a terminal, resolved to `Always` or `Never` by the binary
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl FromStr for ColorChoice {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(ColorChoice::Auto),
+            "always" => Ok(ColorChoice::Always),
+            "never" => Ok(ColorChoice::Never),
+            _ => Err(format!("unknown color mode {}", s)),
+        }
+    }
+}
+
+// Options only counting reads, rejected by --frequency and --diff, which
+// print something else entirely
+const COUNTING_OPTIONS: &[&str] = &[
+    "lines",
+    "words",
+    "bytes",
+    "chars",
+    "longest-line",
+    "longest-line-number",
+    "shortest-line",
+    "avg-line-length",
+    "longest-word",
+    "max-words-per-line",
+    "unique",
+    "unique-global",
+    "record-bytes",
+    "count-byte",
+    "blank-lines",
+    "paragraphs",
+    "unicode-words",
+    "graphemes",
+    "encoding",
+    "skip-binary",
+    "text",
+    "decompress",
+    "bytes-range",
+    "skip-bom",
+    "ascii",
+    "tab-width",
+    "count-partial-line",
+    "posix-lines",
+    "crlf",
+    "line-delimiter",
+    "word-separators",
+    "line-match",
+    "skip-lines",
+    "max-lines",
+    "mmap",
+    "buffer-size",
+    "du",
+    "by-extension",
+    "follow",
+    "ignore-missing",
+    "stdin-name",
+    "aggregate",
+    "cache",
+    "threads",
+    "reorder-window",
+    "timings",
+    "count-files",
+    "tee",
+    "progress",
+    "progress-interval",
+    "siginfo-stdout",
+    "siginfo-signal",
+    "human",
+    "limit",
+    "json",
+    "json-lines",
+    "summary-json",
+    "csv",
+    "tabs",
+    "format",
+    "header",
+    "null",
+    "raw-paths",
+    "sort",
+    "wc-compat",
+    "baseline",
+    "explain",
+    "strategy",
+];
+
 #[derive(Debug, Default, StructOpt, Clone)]
 #[structopt(
     name = "cw",
-    about = "Count Words - word, line, character and byte count"
+    about = "Count Words - word, line, character and byte count",
+    after_help = "On Ctrl-C, the rows for files already counted are printed, and the total of \
+                  the input counted so far is printed to stderr before exiting with status 130.  \
+                  A second Ctrl-C quits at once."
 )]
 pub struct Opt {
-    /// Count lines
+    /// Count lines, as the number of newlines like POSIX wc, so "foo\n" is one line and a last
+    /// line without a newline isn't counted unless --count-partial-line
     #[structopt(short, long)]
     pub lines: bool,
-    /// Count words
+    /// Count words, separated by whitespace including Unicode spaces such as U+00A0, with or
+    /// without -m
     #[structopt(short, long)]
     pub words: bool,
     /// Count bytes
@@ -19,19 +305,517 @@ pub struct Opt {
     /// Count bytes (default) or characters (-m) of the longest line
     #[structopt(short = "L", long = "max-line-length")]
     pub longest_line: bool,
-    /// Count UTF-8 characters instead of bytes
+    /// Decode input from this encoding, e.g. utf-16le, utf-16be or latin1.  Byte counts
+    /// remain those of the undecoded input
+    #[structopt(long, parse(try_from_str = parse_encoding))]
+    pub encoding: Option<&'static Encoding>,
+    /// Skip files with a NUL byte in their first 8K, like grep, with a warning.  Skipped
+    /// files don't count as failures for the exit status
+    #[structopt(long = "skip-binary", overrides_with = "text")]
+    pub skip_binary: bool,
+    /// Count binary files as text, overriding --skip-binary
+    #[structopt(long, overrides_with = "skip-binary")]
+    pub text: bool,
+    /// Count the contents of gzip and zstd compressed input, detected by its first bytes.
+    /// Byte counts are then of the decompressed data, not the size on disk.  Requires
+    /// the decompress feature
+    #[structopt(long)]
+    pub decompress: bool,
+    /// Count only the bytes from offset START up to END, either of which may be left out.
+    /// Characters and words at either end of the range may be split
+    #[structopt(long = "bytes-range", value_name = "START..END")]
+    pub bytes_range: Option<ByteRange>,
+    /// Skip a leading UTF-8 byte order mark when counting characters, words and lines.  Its
+    /// bytes are still included in byte counts
+    #[structopt(long = "skip-bom")]
+    pub skip_bom: bool,
+    /// Assume input is ASCII, so characters are counted as bytes by the faster byte paths,
+    /// e.g. for -mw.  Fails as soon as a non-ASCII byte is read
+    #[structopt(long, conflicts_with = "encoding")]
+    pub ascii: bool,
+    /// Expand tabs to multiples of this width when measuring the longest line, or 0 to
+    /// count them as a single column
+    #[structopt(long = "tab-width", default_value = "8")]
+    pub tab_width: u64,
+    /// Also count a last line that doesn't end in a newline, unlike wc, so "foo\nbar" is two
+    /// lines.  Overrides --posix-lines
+    #[structopt(long = "count-partial-line", overrides_with = "posix-lines")]
+    pub count_partial_line: bool,
+    /// Count lines strictly as the number of newlines, the default.  Overrides
+    /// --count-partial-line
+    #[structopt(long = "posix-lines", overrides_with = "count-partial-line")]
+    pub posix_lines: bool,
+    /// Don't count the carriage return of a CRLF line ending towards the longest line.  Has
+    /// no effect with a --line-delimiter other than \n
+    #[structopt(long)]
+    pub crlf: bool,
+    /// End lines with this byte rather than a newline, such as '\0' for NUL-separated
+    /// records or '\r' for classic Mac OS text.  Newlines are then ordinary bytes, so with
+    /// '\r' a CRLF ending is one line break followed by a newline starting the next line.
+    /// Must be an ASCII character, or an escape such as \0, \t or \x1e
+    #[structopt(long = "line-delimiter", value_name = "BYTE", parse(try_from_str = parse_line_delimiter))]
+    pub line_delimiter: Option<u8>,
+    /// Split words on these bytes instead of whitespace, e.g. ",;\t".  Lines still end
+    /// at newlines, and -L still measures whole lines, separators and all.  Not supported
+    /// with -m
+    #[structopt(long = "word-separators", value_name = "BYTES")]
+    pub word_separators: Option<ByteSet>,
+    /// Also report the line number of the longest line
+    #[structopt(long = "longest-line-number")]
+    pub longest_line_number: bool,
+    /// Count bytes (default) or characters (-m) of the shortest line
+    #[structopt(long = "min-line-length")]
+    pub shortest_line: bool,
+    /// Report the mean bytes (default) or characters (-m) per line
+    #[structopt(long = "avg-line-length")]
+    pub avg_line_length: bool,
+    /// Report the length of the longest word, in characters
+    #[structopt(long = "longest-word")]
+    pub longest_word: bool,
+    /// Report the most words on any one line
+    #[structopt(long = "max-words-per-line")]
+    pub max_words_per_line: bool,
+    /// Count distinct lines in each input.
+    ///
+    /// Every distinct line is remembered as a 64-bit hash, so memory use grows with the number
+    /// of distinct lines.
+    #[structopt(long)]
+    pub unique: bool,
+    /// Like --unique, but the total counts lines that are distinct across every input
+    #[structopt(long = "unique-global")]
+    pub unique_global: bool,
+    /// Print the most frequent words and how often they occur, instead of counts
+    #[structopt(
+        long,
+        conflicts_with_all = COUNTING_OPTIONS,
+        conflicts_with_all = &["total", "no-total"]
+    )]
+    pub frequency: bool,
+    /// Read input as unified diffs and print the lines added and removed in each file they
+    /// change, instead of counts
+    #[structopt(
+        long,
+        conflicts_with_all = COUNTING_OPTIONS,
+        conflicts_with_all = &["frequency", "top"]
+    )]
+    pub diff: bool,
+    /// Ignore case when comparing words for --frequency
+    #[structopt(long = "case-insensitive", requires = "frequency")]
+    pub case_insensitive: bool,
+    /// Count UTF-8 characters instead of bytes.  In invalid UTF-8 each byte is a character,
+    /// except stray continuation bytes
     #[structopt(short = "m", long, overrides_with = "bytes", multiple = true)]
     pub chars: bool,
-    /// Number of counting threads to spawn
+    /// Only count lines matching a regular expression.
+    ///
+    /// Input is then read a line at a time, which is slower than usual.
+    #[structopt(long = "match", value_name = "REGEX")]
+    pub line_match: Option<Regex>,
+    /// Only count lines not matching the --match regular expression
+    #[structopt(long = "invert-match", requires = "line-match")]
+    pub invert_match: bool,
+    /// Skip this many lines of each input before counting
+    #[structopt(long = "skip-lines", value_name = "N", default_value = "0")]
+    pub skip_lines: u64,
+    /// Count at most this many lines of each input, after any --skip-lines
+    #[structopt(long = "max-lines", value_name = "N")]
+    pub max_lines: Option<u64>,
+    /// Count fixed-size records of this many bytes, warning of any left over
+    #[structopt(long = "record-bytes", value_name = "N")]
+    pub record_bytes: Option<NonZeroU64>,
+    /// Count occurrences of this byte, which may be an escape such as \t, \0 or \x1e.
+    /// Repeat to count several bytes
+    #[structopt(long = "count-byte", value_name = "BYTE", number_of_values = 1, parse(try_from_str = parse_byte))]
+    pub count_byte: Vec<u8>,
+    /// Count empty or whitespace-only lines
+    #[structopt(long = "blank-lines")]
+    pub blank_lines: bool,
+    /// Count paragraphs, blocks of text separated by blank lines
+    #[structopt(long)]
+    pub paragraphs: bool,
+    /// Count words using Unicode (UAX#29) word boundaries
+    #[structopt(long = "unicode-words")]
+    pub unicode_words: bool,
+    /// Count Unicode grapheme clusters (user-perceived characters)
+    #[structopt(long)]
+    pub graphemes: bool,
+    /// Memory-map regular files instead of reading them
+    #[structopt(long)]
+    pub mmap: bool,
+    /// Expand glob patterns in input arguments, e.g. '*.rs'
+    #[structopt(long)]
+    pub glob: bool,
+    /// Count every regular file beneath any directory inputs
+    #[structopt(short = "r", long)]
+    pub recursive: bool,
+    /// Follow symbolic links when recursing into directories
+    #[structopt(long = "follow-symlinks", requires = "recursive")]
+    pub follow_symlinks: bool,
+    /// Skip files ignored by .gitignore, .ignore and the like when recursing, and hidden files
+    #[structopt(long, requires = "recursive")]
+    pub gitignore: bool,
+    /// Include hidden files with --gitignore
+    #[structopt(long, requires = "gitignore")]
+    pub hidden: bool,
+    /// Report each directory input as a single row summing the counts of the regular files
+    /// beneath it, like du.  Other file types are ignored, and with just -c files are sized
+    /// without being opened
+    #[structopt(long, requires = "recursive")]
+    pub du: bool,
+    /// Print a row for each file extension, such as rs, summing the counts of every input
+    /// with it, instead of a row per input.  Inputs without one are grouped as (none).
+    /// Rows are in order of extension, or as given by --sort
+    #[structopt(long = "by-extension")]
+    pub by_extension: bool,
+    /// Keep counting a single file as it grows, like tail -f, printing its counts again each
+    /// time more whole lines are appended.  Runs until interrupted.  Metrics that need the
+    /// whole file at once, like --paragraphs and --unique, aren't supported
+    #[structopt(
+        long,
+        conflicts_with_all = &[
+            "paragraphs", "unique", "unique-global", "max-lines", "bytes-range", "record-bytes",
+            "decompress", "encoding", "skip-bom", "tee", "du", "by-extension", "summary-json",
+            "frequency", "diff"
+        ]
+    )]
+    pub follow: bool,
+    /// Skip inputs matching a glob, such as '*.min.js'.
+    ///
+    /// Patterns are matched against the file name, or against the whole path if they contain a
+    /// '/'.  May be given more than once.
+    #[structopt(long, value_name = "GLOB", number_of_values = 1, parse(try_from_str = glob::Pattern::new))]
+    pub exclude: Vec<glob::Pattern>,
+    /// Count each input only once, even if it's named more than once.
+    ///
+    /// Paths are compared after resolving symlinks, or as given if that fails.
+    #[structopt(long = "unique-paths")]
+    pub unique_paths: bool,
+    /// Count files that don't exist as empty, rather than failing.
+    ///
+    /// They still get a row of zeros, and count as inputs when deciding whether to print a total.
+    #[structopt(long = "ignore-missing")]
+    pub ignore_missing: bool,
+    /// When to print a total: auto (with more than one input), always, never or only
+    #[structopt(
+        long,
+        default_value = "auto",
+        possible_values = &["auto", "always", "never", "only"]
+    )]
+    pub total: Total,
+    /// Never print a total, the same as --total=never
+    #[structopt(long = "no-total", conflicts_with = "total")]
+    pub no_total: bool,
+    /// Print a single row combining every input, labelled with the first path
+    #[structopt(long, conflicts_with_all = &["total", "no-total"])]
+    pub aggregate: bool,
+    /// Label for the --aggregate row
+    #[structopt(long = "aggregate-name", value_name = "NAME", requires = "aggregate")]
+    pub aggregate_name: Option<String>,
+    /// Reuse the counts of files whose size and modification time haven't changed since they
+    /// were saved to this file by a previous run with the same counting options.
+    ///
+    /// A file changed without changing either would be counted from stale data.  The file is
+    /// rewritten with the counts of this run's files.
+    #[structopt(
+        long,
+        value_name = "FILE",
+        parse(from_os_str),
+        conflicts_with = "unique-global"
+    )]
+    pub cache: Option<PathBuf>,
+    /// Number of counting threads to spawn, or 0 for one per logical CPU.
+    ///
+    /// Files are spread across threads.  Standard input read by default is split between
+    /// them in chunks only when counting just lines and bytes, since words and line lengths
+    /// can straddle the edge of a chunk.
     #[structopt(long, default_value = "1")]
     pub threads: usize,
+    /// With --threads, the most inputs that may be counted ahead of the next one to print
+    #[structopt(long = "reorder-window", value_name = "N", default_value = "1024")]
+    pub reorder_window: usize,
+    /// Read input in blocks of this size, e.g. 256K or 1M, rather than 32K.  It also caps
+    /// the memory used for a line in the multibyte -m and -w paths, which split longer lines
+    #[structopt(long = "buffer-size", value_name = "BYTES", parse(try_from_str = parse_size))]
+    pub buffer_size: Option<NonZeroUsize>,
+    /// Print the time spent counting each file to stderr
+    #[structopt(long)]
+    pub timings: bool,
+    /// Print how many of the inputs were counted to stderr once they're all done.  This is
+    /// apart from the total, so it's still printed with --no-total
+    #[structopt(long = "count-files")]
+    pub count_files: bool,
+    /// Pass standard input through to stdout unchanged, printing counts to stderr instead.
+    /// Input is written out as it's read, and counts follow once all of it has been written
+    /// and stdout flushed, even if counting stopped early as with --max-lines
+    #[structopt(
+        long,
+        conflicts_with_all = &["input", "files-from", "files0-from", "files-list", "frequency"]
+    )]
+    pub tee: bool,
+    /// Read each file this many times before timing its count, to warm the page cache
+    #[structopt(long, value_name = "N", requires = "timings", hidden = true)]
+    pub warmup: Option<u32>,
+    /// Show how many files have been processed on stderr, if it's a terminal
+    #[structopt(long)]
+    pub progress: bool,
+    /// Print in-progress counts on --siginfo-signal to stdout instead of stderr.
+    ///
+    /// These are interleaved with the normal output.
+    #[structopt(long = "siginfo-stdout")]
+    pub siginfo_stdout: bool,
+    /// Print in-progress counts on this signal, such as USR2, instead of SIGINFO where there is
+    /// one and SIGUSR1
+    #[structopt(
+        long = "siginfo-signal",
+        value_name = "NAME",
+        parse(try_from_str = crate::siginfo::parse_signal)
+    )]
+    pub siginfo_signal: Option<i32>,
+    /// Print in-progress counts every this many seconds, as with SIGINFO, or 0 to disable
+    #[structopt(long = "progress-interval", value_name = "SECS", default_value = "0")]
+    pub progress_interval: u64,
+    /// Print byte counts in human-readable base-1024 units, e.g. 1.2M
+    #[structopt(short = "H", long)]
+    pub human: bool,
+    /// Print no counts, only errors, for checking every input can be read by the exit status
+    #[structopt(short, long)]
+    pub quiet: bool,
+    /// Fail if any input has more than N of a metric, given as METRIC=N, such as lines=500.
+    ///
+    /// Repeat to set limits on several metrics, any one of which failing is a failure.  Each
+    /// input over a limit is reported, and counts are still printed unless --quiet.  The
+    /// metric must be one being counted.
+    #[structopt(long, value_name = "METRIC=N", number_of_values = 1)]
+    pub limit: Vec<Limit>,
+    /// Color paths and the largest value in each column: auto (when stdout is a terminal,
+    /// or CLICOLOR_FORCE is set, unless NO_COLOR is), always or never
+    #[structopt(
+        long,
+        value_name = "WHEN",
+        default_value = "auto",
+        possible_values = &["auto", "always", "never"]
+    )]
+    pub color: ColorChoice,
+    /// Terminate each output record with a NUL byte instead of a newline
+    #[structopt(short = "0", long)]
+    pub null: bool,
+    /// Print paths as their raw bytes, rather than replacing any invalid UTF-8.  Implied
+    /// by -0.  Only affects Unix, and not JSON or CSV output
+    #[structopt(long = "raw-paths")]
+    pub raw_paths: bool,
+    /// Output counts as a JSON array of objects
+    #[structopt(long, overrides_with_all = &["json-lines", "csv", "tabs"])]
+    pub json: bool,
+    /// Output counts as newline-delimited JSON objects
+    #[structopt(long = "json-lines", overrides_with_all = &["json", "csv", "tabs"])]
+    pub json_lines: bool,
+    /// Output only the total of all inputs as a single JSON object, with the number of
+    /// inputs counted as "files"
+    #[structopt(
+        long = "summary-json",
+        conflicts_with_all = &[
+            "json", "json-lines", "csv", "tabs", "format", "header", "wc-compat", "aggregate", "total",
+            "no-total", "quiet"
+        ]
+    )]
+    pub summary_json: bool,
+    /// Print errors with inputs to stderr as JSON objects with path and error fields, one
+    /// per line, for consumers of --json or --json-lines
+    #[structopt(long = "json-errors")]
+    pub json_errors: bool,
+    /// Output counts as comma-separated values with a header row
+    #[structopt(long, overrides_with_all = &["json", "json-lines", "tabs"])]
+    pub csv: bool,
+    /// Separate output fields with a single tab, without alignment
+    #[structopt(long, overrides_with_all = &["json", "json-lines", "csv"])]
+    pub tabs: bool,
+    /// Output each record using a template, e.g. '{lines}\t{path}'.
+    ///
+    /// Placeholders are {path} and the name of any metric, as used in the CSV header: {lines},
+    /// {words}, {bytes}, {chars}, {longest_line} and so on.  \t, \n and \\ escapes are
+    /// recognised, and each record ends with a newline, or NUL with --null.
+    #[structopt(
+        long,
+        parse(try_from_str = Template::parse),
+        conflicts_with_all = &["json", "json-lines", "csv", "tabs"]
+    )]
+    pub format: Option<Template>,
+    /// Order rows by a metric, such as lines or bytes, or by path
+    #[structopt(long, value_name = "FIELD", parse(try_from_str = SortKey::parse))]
+    pub sort: Option<SortKey>,
+    /// Sort in descending order
+    #[structopt(long, requires = "sort")]
+    pub reverse: bool,
+    /// Only print the N rows with the largest values of the --sort field, or the smallest with
+    /// --reverse, or the N most frequent words with --frequency (20 by default).
+    ///
+    /// The total still includes every input.
+    #[structopt(long, value_name = "N")]
+    pub top: Option<usize>,
+    /// Print a header row labelling each column
+    #[structopt(long, conflicts_with_all = &["json", "json-lines", "csv", "format"])]
+    pub header: bool,
+    /// Print counts exactly as BSD wc does, each padded to 7 columns rather than aligned to
+    /// the widest, and a lone count from standard input padded too
+    #[structopt(
+        long = "wc-compat",
+        conflicts_with_all = &["json", "json-lines", "csv", "tabs", "format", "header", "human"]
+    )]
+    pub wc_compat: bool,
+    /// Print each count as the difference from a previous run saved with --json,
+    /// --json-lines or --csv, such as +42 or -3.
+    ///
+    /// Files missing from the baseline show their full counts, and files in the
+    /// baseline which weren't counted are listed with their counts negated.
+    #[structopt(
+        long,
+        value_name = "FILE",
+        parse(from_os_str),
+        conflicts_with_all = &["json", "json-lines", "csv", "format"]
+    )]
+    pub baseline: Option<PathBuf>,
     /// Read input from the newline-terminated list of filenames in the given file.
     #[structopt(long = "files-from", parse(from_os_str))]
     pub files_from: Option<PathBuf>,
     /// Read input from the NUL-terminated list of filenames in the given file.
     #[structopt(long = "files0-from", parse(from_os_str))]
     pub files0_from: Option<PathBuf>,
-    /// Input files
+    /// Read input from the list of filenames in the given file, separated by
+    /// --files-delimiter.  A path of - reads the list from stdin.
+    #[structopt(long = "files-list", value_name = "PATH", parse(from_os_str))]
+    pub files_list: Option<PathBuf>,
+    /// The byte separating filenames in --files-list, which may be an escape such as \t
+    /// or \0.  Defaults to newline
+    #[structopt(long = "files-delimiter", value_name = "BYTE", requires = "files-list", parse(try_from_str = parse_byte))]
+    pub files_delimiter: Option<u8>,
+    /// Print version information, including the SIMD support compiled in
+    #[structopt(long = "version-verbose")]
+    pub version_verbose: bool,
+    /// Check the SIMD counting paths against naive counts and exit
+    #[structopt(long = "self-test", hidden = true)]
+    pub self_test: bool,
+    /// Print which counting strategy the other options select and why, then exit without
+    /// counting anything
+    #[structopt(long)]
+    pub explain: bool,
+    /// Count with this strategy, as named by --explain, rather than the one the options
+    /// select.  For benchmarking and diagnosing bugs in a particular strategy; fails if it
+    /// can't count what's asked for
+    #[structopt(long, value_name = "NAME", hidden = true)]
+    pub strategy: Option<Strategy>,
+    /// Label standard input with this name in the path column, both when it's read by
+    /// default and when given as -
+    #[structopt(long = "stdin-name", value_name = "NAME", parse(from_os_str))]
+    pub stdin_name: Option<PathBuf>,
+    /// Input files, where - means standard input
     #[structopt(parse(from_os_str))]
     pub input: Vec<PathBuf>,
 }
+
+impl Opt {
+    /// Configure an `Opt` for library use, without parsing arguments.
+    ///
+    /// The builder starts from `Opt::from_iter(["cw"])`, the command line's
+    /// defaults, such as a `tab_width` of 8.  No metrics are enabled, so only
+    /// bytes are counted until others are turned on:
+    ///
+    /// ```
+    /// let opt = cw::Opt::builder().lines(true).words(true).build();
+    ///
+    /// let counts = cw::count_reader(&b"one two\nthree\n"[..], &opt).unwrap();
+    /// assert_eq!((counts.lines, counts.words), (2, 3));
+    /// ```
+    ///
+    /// Not every combination can be counted, such as `words` split on
+    /// `word_separators` while counting `chars`.  `Strategy::try_from(&opt)`
+    /// reports whether one can.
+    pub fn builder() -> OptBuilder {
+        OptBuilder::default()
+    }
+}
+
+macro_rules! opt_setters {
+    ($($name:ident: $ty:ty,)+) => {
+        $(
+            #[doc = concat!("Set [`Opt::", stringify!($name), "`].")]
+            pub fn $name(mut self, $name: $ty) -> Self {
+                self.opt.$name = $name;
+                self
+            }
+        )+
+    };
+}
+
+/// Builds an `Opt` for counting and printing, as returned by `Opt::builder`.
+///
+/// Options only meaningful to the `cw` binary, like which files to read and
+/// how many threads to use, are left to struct update syntax.
+#[derive(Debug, Clone)]
+pub struct OptBuilder {
+    opt: Opt,
+}
+
+impl Default for OptBuilder {
+    // Parsed from an empty command line, so defaults match `cw` rather than
+    // those derived for `Opt`
+    fn default() -> Self {
+        Self {
+            opt: Opt::from_iter(["cw"]),
+        }
+    }
+}
+
+impl OptBuilder {
+    opt_setters! {
+        lines: bool,
+        words: bool,
+        bytes: bool,
+        chars: bool,
+        longest_line: bool,
+        longest_line_number: bool,
+        shortest_line: bool,
+        avg_line_length: bool,
+        longest_word: bool,
+        max_words_per_line: bool,
+        unique: bool,
+        unique_global: bool,
+        blank_lines: bool,
+        paragraphs: bool,
+        unicode_words: bool,
+        graphemes: bool,
+        encoding: Option<&'static Encoding>,
+        decompress: bool,
+        bytes_range: Option<ByteRange>,
+        skip_binary: bool,
+        skip_bom: bool,
+        ascii: bool,
+        tab_width: u64,
+        crlf: bool,
+        count_partial_line: bool,
+        line_delimiter: Option<u8>,
+        word_separators: Option<ByteSet>,
+        line_match: Option<Regex>,
+        invert_match: bool,
+        skip_lines: u64,
+        max_lines: Option<u64>,
+        record_bytes: Option<NonZeroU64>,
+        count_byte: Vec<u8>,
+        mmap: bool,
+        ignore_missing: bool,
+        human: bool,
+        color: ColorChoice,
+        null: bool,
+        raw_paths: bool,
+        json: bool,
+        json_lines: bool,
+        csv: bool,
+        tabs: bool,
+        format: Option<Template>,
+        header: bool,
+        wc_compat: bool,
+    }
+
+    pub fn build(self) -> Opt {
+        self.opt
+    }
+}
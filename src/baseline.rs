@@ -0,0 +1,295 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::count::Counts;
+
+/// Counts saved from an earlier run with `--json`, `--json-lines` or `--csv`,
+/// for printing differences against with `--baseline`.
+///
+/// Rows are keyed by their path as printed, including any `total`.
+#[derive(Debug, Default)]
+pub struct Baseline {
+    counts: HashMap<PathBuf, Counts>,
+    seen: HashSet<PathBuf>,
+}
+
+impl Baseline {
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let text = fs::read_to_string(path)?;
+        text.parse()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Note that `path` has been counted, so it isn't one of the `removed_paths`
+    pub fn mark_seen(&mut self, path: &Path) {
+        if self.counts.contains_key(path) {
+            self.seen.insert(path.to_path_buf());
+        }
+    }
+
+    pub fn get(&self, path: &Path) -> Option<&Counts> {
+        self.counts.get(path)
+    }
+
+    /// The sorted paths of files in the baseline which haven't been seen, and
+    /// presumably no longer exist.
+    pub fn removed_paths(&self) -> Vec<PathBuf> {
+        let mut removed: Vec<PathBuf> = self
+            .counts
+            .keys()
+            .filter(|path| *path != Path::new("total") && !self.seen.contains(*path))
+            .cloned()
+            .collect();
+        removed.sort();
+        removed
+    }
+
+    pub fn len(&self) -> usize {
+        self.counts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.counts.is_empty()
+    }
+
+    fn from_rows(rows: Vec<Counts>) -> Self {
+        let counts = rows
+            .into_iter()
+            .filter_map(|count| count.path.clone().map(|path| (path, count)))
+            .collect();
+
+        Self {
+            counts,
+            seen: HashSet::new(),
+        }
+    }
+}
+
+impl std::str::FromStr for Baseline {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let rows = match s.trim_start().chars().next() {
+            Some('[') | Some('{') => parse_json(s)?,
+            Some(_) => parse_csv(s)?,
+            None => vec![],
+        };
+
+        Ok(Self::from_rows(rows))
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Punct(char),
+    Str(String),
+    Num(u64),
+}
+
+// Just enough JSON for what print_json writes: objects of strings, whole
+// numbers and arrays of them, either in an array or one per line.
+fn tokenize(s: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = vec![];
+    let mut chars = s.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => (),
+            '{' | '}' | '[' | ']' | ':' | ',' => tokens.push(Token::Punct(c)),
+            '"' => {
+                let mut string = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => string.push(match chars.next() {
+                            Some('n') => '\n',
+                            Some('r') => '\r',
+                            Some('t') => '\t',
+                            Some('u') => {
+                                let hex: String = chars.by_ref().take(4).collect();
+                                u32::from_str_radix(&hex, 16)
+                                    .ok()
+                                    .and_then(std::char::from_u32)
+                                    .unwrap_or(std::char::REPLACEMENT_CHARACTER)
+                            }
+                            Some(c @ '"') | Some(c @ '\\') | Some(c @ '/') => c,
+                            _ => return Err("invalid escape in JSON string".to_string()),
+                        }),
+                        Some(c) => string.push(c),
+                        None => return Err("unterminated JSON string".to_string()),
+                    }
+                }
+                tokens.push(Token::Str(string));
+            }
+            '0'..='9' => {
+                let mut n = u64::from(c.to_digit(10).unwrap_or(0));
+                while let Some(d) = chars.peek().and_then(|c| c.to_digit(10)) {
+                    n = n
+                        .checked_mul(10)
+                        .and_then(|n| n.checked_add(u64::from(d)))
+                        .ok_or("number too large in JSON")?;
+                    chars.next();
+                }
+                tokens.push(Token::Num(n));
+            }
+            c => return Err(format!("unexpected {:?} in JSON", c)),
+        }
+    }
+
+    Ok(tokens)
+}
+
+fn parse_json(s: &str) -> Result<Vec<Counts>, String> {
+    let mut rows = vec![];
+    let mut tokens = tokenize(s)?.into_iter();
+
+    while let Some(token) = tokens.next() {
+        if token != Token::Punct('{') {
+            continue;
+        }
+
+        let mut count = Counts::default();
+        loop {
+            let key = match tokens.next() {
+                Some(Token::Punct('}')) => break,
+                Some(Token::Punct(',')) => continue,
+                Some(Token::Str(key)) => key,
+                _ => return Err("expected a key in JSON object".to_string()),
+            };
+
+            if tokens.next() != Some(Token::Punct(':')) {
+                return Err(format!("expected : after {:?} in JSON", key));
+            }
+
+            match tokens.next() {
+                Some(Token::Str(path)) if key == "path" => count.path = Some(path.into()),
+                Some(Token::Str(_)) => (),
+                Some(Token::Num(n)) => {
+                    count.set_metric(&key, n);
+                }
+                Some(Token::Punct('[')) => loop {
+                    match tokens.next() {
+                        Some(Token::Num(n)) => {
+                            count.set_metric(&key, n);
+                        }
+                        Some(Token::Punct(',')) => (),
+                        Some(Token::Punct(']')) => break,
+                        _ => return Err(format!("expected numbers in {:?}", key)),
+                    }
+                },
+                _ => return Err(format!("expected a value for {:?} in JSON", key)),
+            }
+        }
+
+        rows.push(count);
+    }
+
+    Ok(rows)
+}
+
+// Records as print_csv writes them, where only the path is ever quoted
+fn csv_records(s: &str) -> Vec<Vec<String>> {
+    let mut records = vec![];
+    let mut record = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = s.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => record.push(std::mem::take(&mut field)),
+            '\r' if !quoted && chars.peek() == Some(&'\n') => (),
+            '\n' | '\0' if !quoted => {
+                record.push(std::mem::take(&mut field));
+                records.push(std::mem::take(&mut record));
+            }
+            c => field.push(c),
+        }
+    }
+
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+
+    records
+}
+
+fn parse_csv(s: &str) -> Result<Vec<Counts>, String> {
+    let mut records = csv_records(s).into_iter();
+    let header = records.next().unwrap_or_default();
+
+    if !header.iter().any(|name| name == "path") {
+        return Err("expected a CSV header with a path column".to_string());
+    }
+
+    records
+        .map(|record| {
+            let mut count = Counts::default();
+            for (name, field) in header.iter().zip(record) {
+                if name == "path" {
+                    count.path = Some(field.into());
+                } else if !field.is_empty() {
+                    let value = field
+                        .parse()
+                        .map_err(|_| format!("invalid {} {:?} in CSV", name, field))?;
+                    count.set_metric(name, value);
+                }
+            }
+            Ok(count)
+        })
+        .collect()
+}
+
+#[test]
+fn test_parse_json() {
+    let json = r#"[
+  {"path":"a \"b\".txt","lines":2,"bytes":6,"byte_matches":[1,3]},
+  {"path":"total","lines":5}
+]
+"#;
+    let baseline: Baseline = json.parse().unwrap();
+    assert_eq!(baseline.len(), 2);
+
+    let a = baseline.get(Path::new("a \"b\".txt")).unwrap();
+    assert_eq!(a.lines, 2);
+    assert_eq!(a.bytes, 6);
+    assert_eq!(a.byte_matches, vec![1, 3]);
+    assert_eq!(baseline.get(Path::new("total")).unwrap().lines, 5);
+
+    let lines: Baseline = "{\"path\":\"a\",\"words\":1}\n{\"path\":\"b\",\"words\":2}\n"
+        .parse()
+        .unwrap();
+    assert_eq!(lines.get(Path::new("b")).unwrap().words, 2);
+
+    assert!("[{\"path\":\"a\",\"lines\":-1}]"
+        .parse::<Baseline>()
+        .is_err());
+}
+
+#[test]
+fn test_parse_csv() {
+    let csv = "lines,words,bytes,path\n2,,6,\"a,\"\"b\"\".txt\"\r\n3,4,9,c\n";
+    let mut baseline: Baseline = csv.parse().unwrap();
+
+    let a = baseline.get(Path::new("a,\"b\".txt")).unwrap();
+    assert_eq!(a.lines, 2);
+    assert_eq!(a.words, 0);
+    assert_eq!(a.bytes, 6);
+
+    assert_eq!(baseline.get(Path::new("c")).unwrap().words, 4);
+    baseline.mark_seen(Path::new("c"));
+    assert_eq!(baseline.removed_paths(), vec![PathBuf::from("a,\"b\".txt")]);
+    baseline.mark_seen(Path::new("a,\"b\".txt"));
+    assert!(baseline.removed_paths().is_empty());
+
+    assert!("lines,words\n1,2\n".parse::<Baseline>().is_err());
+    assert!("lines,path\nx,a\n".parse::<Baseline>().is_err());
+}
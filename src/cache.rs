@@ -0,0 +1,283 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, Metadata};
+use std::hash::{Hash, Hasher};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
+
+use crate::args::Opt;
+use crate::count::Counts;
+
+const MAGIC: &str = "cw-cache 1";
+
+/// Counts of files from a previous run, for `--cache`, reused for any file
+/// whose size and modification time haven't changed.
+///
+/// A file rewritten with the same size within the timestamp resolution of its
+/// filesystem, or with its modification time put back, would be counted from
+/// stale data.  Counting with different options discards the whole cache.
+///
+/// The cache file is a header line identifying the options, followed by a
+/// NUL-terminated record for each file of its modification time, size, every
+/// metric and its canonical path, separated by spaces.
+#[derive(Debug)]
+pub struct Cache {
+    options: String,
+    entries: Mutex<HashMap<PathBuf, Entry>>,
+    // Only files counted this run are saved, so removed files don't linger
+    seen: Mutex<HashMap<PathBuf, Entry>>,
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    modified: u128,
+    size: u64,
+    counts: Counts,
+}
+
+impl Entry {
+    fn matches(&self, meta: &Metadata) -> bool {
+        Some(self.modified) == modified(meta) && self.size == meta.len()
+    }
+}
+
+// Nanoseconds since the epoch, where the platform supports it
+fn modified(meta: &Metadata) -> Option<u128> {
+    meta.modified()
+        .ok()?
+        .duration_since(UNIX_EPOCH)
+        .ok()
+        .map(|d| d.as_nanos())
+}
+
+// Identify the options that affect the counts of a file
+fn options_key(opt: &Opt) -> String {
+    let options: [&dyn fmt::Debug; 33] = [
+        &opt.lines,
+        &opt.words,
+        &opt.bytes,
+        &opt.chars,
+        &opt.longest_line,
+        &opt.longest_line_number,
+        &opt.shortest_line,
+        &opt.avg_line_length,
+        &opt.longest_word,
+        &opt.max_words_per_line,
+        &opt.unique,
+        &opt.case_insensitive,
+        &opt.blank_lines,
+        &opt.paragraphs,
+        &opt.unicode_words,
+        &opt.graphemes,
+        &opt.encoding,
+        &opt.skip_binary,
+        &opt.decompress,
+        &opt.bytes_range,
+        &opt.skip_bom,
+        &opt.ascii,
+        &opt.tab_width,
+        &opt.count_partial_line,
+        &opt.crlf,
+        &opt.line_delimiter,
+        &opt.word_separators,
+        &opt.line_match,
+        &opt.invert_match,
+        &opt.skip_lines,
+        &opt.max_lines,
+        &opt.record_bytes,
+        &opt.count_byte,
+    ];
+
+    let mut hasher = DefaultHasher::new();
+    format!("{:?}", options).hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+impl Cache {
+    /// An empty cache for counts made with `opt`
+    pub fn new(opt: &Opt) -> Self {
+        Self {
+            options: options_key(opt),
+            entries: Mutex::new(HashMap::new()),
+            seen: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Load the cache at `path` for counts made with `opt`, which is empty if
+    /// the file doesn't exist or was made with other options.
+    pub fn load<P: AsRef<Path>>(path: P, opt: &Opt) -> io::Result<Self> {
+        let cache = Self::new(opt);
+
+        let data = match fs::read(path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(cache),
+            Err(e) => return Err(e),
+        };
+        let data = String::from_utf8(data)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "cache is not UTF-8"))?;
+
+        let mut records = data.split_terminator('\0');
+        if records.next() != Some(&format!("{} {}\n", MAGIC, cache.options)) {
+            return Ok(cache);
+        }
+
+        let names: Vec<_> = Counts::default()
+            .metrics(opt)
+            .into_iter()
+            .map(|(name, _, _)| name)
+            .collect();
+
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid cache record");
+        let mut entries = cache.entries.lock().expect("cache lock");
+        for record in records {
+            let mut fields = record.splitn(names.len() + 3, ' ');
+            let modified = fields
+                .next()
+                .and_then(|n| n.parse().ok())
+                .ok_or_else(invalid)?;
+            let size = fields
+                .next()
+                .and_then(|n| n.parse().ok())
+                .ok_or_else(invalid)?;
+            let mut counts = Counts::default();
+            for name in &names {
+                match fields.next() {
+                    // A shortest line of a file without any
+                    Some("-") => (),
+                    Some(n) => {
+                        counts.set_metric(name, n.parse().map_err(|_| invalid())?);
+                    }
+                    None => return Err(invalid()),
+                }
+            }
+
+            let path = fields.next().ok_or_else(invalid)?;
+            entries.insert(
+                PathBuf::from(path),
+                Entry {
+                    modified,
+                    size,
+                    counts,
+                },
+            );
+        }
+        drop(entries);
+
+        Ok(cache)
+    }
+
+    /// The cached counts of `path`, labelled with it, if it's unchanged
+    pub fn get(&self, path: &Path) -> Option<Counts> {
+        let key = fs::canonicalize(path).ok()?;
+        let meta = fs::metadata(&key).ok()?;
+
+        let entry = self
+            .entries
+            .lock()
+            .expect("cache lock")
+            .get(&key)
+            .filter(|entry| entry.matches(&meta))
+            .cloned()?;
+
+        let mut counts = entry.counts.clone();
+        counts.path = Some(path.to_path_buf());
+        self.seen.lock().expect("cache lock").insert(key, entry);
+        Some(counts)
+    }
+
+    /// Remember the counts of `path`, taken from a file as it was when `meta`
+    /// was read, so a file changing mid-count isn't trusted next time
+    pub fn insert(&self, path: &Path, meta: &Metadata, counts: &Counts) {
+        let (key, modified) = match (fs::canonicalize(path), modified(meta)) {
+            (Ok(key), Some(modified)) => (key, modified),
+            _ => return,
+        };
+
+        let entry = Entry {
+            modified,
+            size: meta.len(),
+            counts: Counts {
+                path: None,
+                ..counts.clone()
+            },
+        };
+        self.seen.lock().expect("cache lock").insert(key, entry);
+    }
+
+    /// Write the counts of every file seen this run to `path`
+    pub fn save<P: AsRef<Path>>(&self, path: P, opt: &Opt) -> io::Result<()> {
+        let mut out = io::BufWriter::new(fs::File::create(path)?);
+        write!(&mut out, "{} {}\n\0", MAGIC, self.options)?;
+
+        for (key, entry) in self.seen.lock().expect("cache lock").iter() {
+            // Records can't hold a path which isn't UTF-8 or contains NUL
+            let key = match key.to_str() {
+                Some(key) if !key.contains('\0') => key,
+                _ => continue,
+            };
+
+            write!(&mut out, "{} {}", entry.modified, entry.size)?;
+            for (name, _, value) in entry.counts.metrics(opt) {
+                if name == "shortest_line" && entry.counts.shortest_line.is_none() {
+                    write!(&mut out, " -")?;
+                } else {
+                    write!(&mut out, " {}", value)?;
+                }
+            }
+            write!(&mut out, " {}\0", key)?;
+        }
+
+        out.flush()
+    }
+}
+
+#[test]
+fn test_cache() {
+    let dir = std::env::temp_dir();
+    let file = dir.join(format!("cw-cache-input-{}", std::process::id()));
+    let saved = dir.join(format!("cw-cache-{}", std::process::id()));
+    fs::write(&file, "a b\nc\n").unwrap();
+
+    let opt = Opt {
+        lines: true,
+        words: true,
+        count_byte: vec![b'a'],
+        ..Opt::default()
+    };
+
+    let cache = Cache::load(&saved, &opt).unwrap();
+    assert!(cache.get(&file).is_none());
+
+    let mut counts = Counts::new(&file);
+    counts.lines = 2;
+    counts.words = 3;
+    counts.byte_matches = vec![1];
+    counts.shortest_line = None;
+    cache.insert(&file, &fs::metadata(&file).unwrap(), &counts);
+    cache.save(&saved, &opt).unwrap();
+
+    let cache = Cache::load(&saved, &opt).unwrap();
+    let cached = cache.get(&file).unwrap();
+    assert_eq!(cached.path, Some(file.clone()));
+    assert_eq!(cached.lines, 2);
+    assert_eq!(cached.words, 3);
+    assert_eq!(cached.byte_matches, vec![1]);
+    assert_eq!(cached.shortest_line, None);
+
+    // Other options mean other counts
+    let lines = Opt {
+        lines: true,
+        ..Opt::default()
+    };
+    assert!(Cache::load(&saved, &lines).unwrap().get(&file).is_none());
+
+    // As does a change in size
+    fs::write(&file, "a b\nc d\n").unwrap();
+    assert!(Cache::load(&saved, &opt).unwrap().get(&file).is_none());
+
+    fs::remove_file(&file).unwrap();
+    fs::remove_file(&saved).unwrap();
+}
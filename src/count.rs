@@ -1,20 +1,41 @@
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::fmt;
 use std::fs::File;
-#[cfg(test)]
-use std::io::Cursor;
-use std::io::{self, BufRead, BufReader, Read, Write};
+use std::io::{self, BufRead, BufReader, Cursor, Read, Seek, SeekFrom, Write};
+use std::num::NonZeroUsize;
+use std::ops::AddAssign;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 use memchr::memchr_iter;
+use memmap2::Mmap;
 
+use ansi_term::Colour;
 use bstr::ByteSlice;
+use encoding_rs::Encoding;
+use unicode_segmentation::UnicodeSegmentation;
 
 const READ_SIZE: usize = 1024 * 32;
 
-use crate::args::Opt;
+// Each thread of count_parallel is handed this much of the stream at a time
+const PARALLEL_CHUNK: usize = 1024 * 1024;
+
+// The leading metrics --csv always has a column for, whether enabled or not
+const CSV_FIXED_COLUMNS: usize = 5;
+
+use crate::args::{ByteRange, ByteSet, ColorChoice, Opt};
+use crate::decode::DecodeReader;
+use crate::decompress::decompress;
+use crate::filter::{
+    non_ascii, AsciiReader, ByteMatchReader, InterruptReader, MatchReader, TeeReader, WindowReader,
+};
+use crate::output::Format;
 use crate::siginfo;
+use crate::unique::{UniqueLines, UniqueReader};
 
 // Open a file configured for fast sequential reading
-fn open_file<P: AsRef<Path>>(path: P) -> io::Result<File> {
+pub(crate) fn open_file<P: AsRef<Path>>(path: P) -> io::Result<File> {
     #[cfg(windows)]
     {
         use std::os::windows::fs::OpenOptionsExt;
@@ -45,14 +66,65 @@ fn open_file<P: AsRef<Path>>(path: P) -> io::Result<File> {
     }
 }
 
-#[derive(Debug, Default)]
+/// The results of counting a single input, or a total across several.
+///
+/// Only the metrics enabled in the `Opt` used to count are meaningful; the
+/// rest are left at zero.
+#[derive(Debug, Default, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct Counts {
+    /// The input these counts are for, or `None` for an unnamed stream
+    #[cfg_attr(feature = "serde", serde(serialize_with = "serialize_path"))]
     pub path: Option<PathBuf>,
+    /// Number of newlines
     pub lines: u64,
+    /// Number of words
     pub words: u64,
+    /// Number of bytes, which is always counted
     pub bytes: u64,
+    /// Number of UTF-8 characters, counting each invalid byte as one unless it's
+    /// a stray continuation byte
     pub chars: u64,
+    /// Length of the longest line, in bytes or characters (with `chars`)
     pub longest_line: u64,
+    /// The 1-based line number of the first longest line
+    pub longest_line_at: u64,
+    /// For totals, the input containing the longest line
+    pub longest_line_path: Option<PathBuf>,
+    /// Length of the shortest line, if there were any lines
+    pub shortest_line: Option<u64>,
+    /// Number of Unicode grapheme clusters
+    pub graphemes: u64,
+    /// Number of empty or whitespace-only lines
+    pub blank_lines: u64,
+    /// Number of blocks of text separated by blank lines
+    pub paragraphs: u64,
+    /// Number of whole fixed-size records, with `record_bytes`
+    pub records: u64,
+    /// Occurrences of each byte in `count_byte`, in the same order
+    pub byte_matches: Vec<u64>,
+    /// Length of the longest word, in characters
+    pub longest_word: u64,
+    /// The most words on any one line
+    pub max_words_per_line: u64,
+    /// Number of distinct lines
+    pub unique_lines: u64,
+    /// With `unique_global`, hashes of the distinct lines, so totals can
+    /// count lines that are distinct across every input
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub line_hashes: Option<Arc<HashSet<u64>>>,
+}
+
+// Paths serialize as strings, lossily if they're not valid UTF-8
+#[cfg(feature = "serde")]
+fn serialize_path<S: serde::Serializer>(
+    path: &Option<PathBuf>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match path {
+        Some(path) => serializer.serialize_some(&path.to_string_lossy()),
+        None => serializer.serialize_none(),
+    }
 }
 
 #[derive(Debug, Default)]
@@ -63,6 +135,54 @@ pub struct Capability {
     bytes: bool,
     chars: bool,
     longest_line: bool,
+    graphemes: bool,
+    blank_lines: bool,
+    paragraphs: bool,
+    longest_word: bool,
+    max_words_per_line: bool,
+    unicode_words: bool,
+    word_separators: bool,
+    // Measures words and line lengths in bytes or chars as requested
+    any_unit: bool,
+}
+
+impl AddAssign<&Counts> for Counts {
+    fn add_assign(&mut self, other: &Counts) {
+        self.add(other);
+    }
+}
+
+/// Every non-zero metric labelled with its name, such as `lines=2 bytes=12 foo`,
+/// followed by any path and no newline.
+///
+/// Use `print` for the columns `cw` prints.
+impl fmt::Display for Counts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut sep = "";
+
+        for (name, _, value, derived) in self.tagged_metrics(&Opt::default()) {
+            // Derived from options as well as counts, so only shown on request
+            if value != 0 && !derived {
+                write!(f, "{}{}={}", sep, name, value)?;
+                sep = " ";
+            }
+        }
+
+        if let Some(ref path) = self.path {
+            write!(f, "{}{}", sep, path.display())?;
+        }
+
+        Ok(())
+    }
+}
+
+// Not imported, so `add` on a Counts always means the inherent method
+impl std::ops::Add<&Counts> for Counts {
+    type Output = Counts;
+
+    fn add(self, other: &Counts) -> Counts {
+        self.merged(other)
+    }
 }
 
 impl Counts {
@@ -73,51 +193,817 @@ impl Counts {
         }
     }
 
+    /// Accumulate `other` into these counts, as for a total.
+    ///
+    /// Most metrics are summed, but line and word lengths take the longest (or
+    /// shortest) of the two, and `path` is left alone.  Also available as `+=`.
     pub fn add(&mut self, other: &Counts) {
         self.lines += other.lines;
         self.words += other.words;
         self.bytes += other.bytes;
         self.chars += other.chars;
-        self.longest_line = std::cmp::max(self.longest_line, other.longest_line);
+        if other.longest_line > self.longest_line {
+            self.longest_line = other.longest_line;
+            self.longest_line_at = other.longest_line_at;
+            self.longest_line_path = other
+                .longest_line_path
+                .clone()
+                .or_else(|| other.path.clone());
+        }
+        self.shortest_line = match (self.shortest_line, other.shortest_line) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        self.graphemes += other.graphemes;
+        self.blank_lines += other.blank_lines;
+        self.paragraphs += other.paragraphs;
+        self.records += other.records;
+        if self.byte_matches.len() < other.byte_matches.len() {
+            self.byte_matches.resize(other.byte_matches.len(), 0);
+        }
+        for (mine, theirs) in self.byte_matches.iter_mut().zip(&other.byte_matches) {
+            *mine += theirs;
+        }
+        self.longest_word = self.longest_word.max(other.longest_word);
+        self.max_words_per_line = self.max_words_per_line.max(other.max_words_per_line);
+
+        match other.line_hashes {
+            Some(ref theirs) => {
+                let mine = Arc::make_mut(self.line_hashes.get_or_insert_with(Default::default));
+                mine.extend(theirs.iter());
+                self.unique_lines = mine.len() as u64;
+            }
+            None => self.unique_lines += other.unique_lines,
+        }
+    }
+
+    /// Like `add`, but returning the combined counts
+    pub fn merged(mut self, other: &Counts) -> Counts {
+        self.add(other);
+        self
+    }
+
+    /// Whether every metric is zero, as for empty input or a fresh `Counts`.
+    /// The path isn't considered.
+    pub fn is_empty(&self) -> bool {
+        self.metrics(&Opt::default())
+            .into_iter()
+            .all(|(_, _, value)| value == 0)
+    }
+
+    /// Zero every metric while keeping `path`, to count another input into
+    /// the same `Counts`.
+    pub fn reset(&mut self) {
+        *self = Self {
+            path: self.path.take(),
+            ..Self::default()
+        };
+    }
+
+    // Record the length of any last line left without a newline at the end of
+    // the input, counting it as a line too with --count-partial-line
+    fn end_partial_line(&mut self, line_len: u64, count_partial: bool) {
+        if line_len > 0 {
+            self.end_line(line_len);
+
+            if count_partial {
+                self.lines += 1;
+            }
+        }
+    }
+
+    // Record the length of a line about to be counted in `lines`
+    fn end_line(&mut self, line_len: u64) {
+        if self.longest_line < line_len {
+            self.longest_line = line_len;
+            self.longest_line_at = self.lines + 1;
+        }
+
+        if self
+            .shortest_line
+            .is_none_or(|shortest| line_len < shortest)
+        {
+            self.shortest_line = Some(line_len);
+        }
+    }
+
+    pub fn print<W: Write>(&self, opt: &Opt, out: W) -> io::Result<()> {
+        self.print_width(opt, 7, out)
+    }
+
+    /// Print with each numeric column right-aligned to `width`
+    pub fn print_width<W: Write>(&self, opt: &Opt, width: usize, out: W) -> io::Result<()> {
+        self.print_highlighted(opt, width, &[], out)
+    }
+
+    /// Like `print_width`, but with `--color` also highlighting columns whose
+    /// value is the one at the same position in `highlight`, such as the largest
+    /// of several inputs.
+    ///
+    /// Color only applies to columnar output.
+    pub fn print_highlighted<W: Write>(
+        &self,
+        opt: &Opt,
+        width: usize,
+        highlight: &[u64],
+        mut out: W,
+    ) -> io::Result<()> {
+        let format = Format::from(opt);
+        match format {
+            Format::Json | Format::JsonLines => {
+                self.print_json(opt, &mut out)?;
+                return end_record(opt, out);
+            }
+            Format::Csv => return self.print_csv(opt, out),
+            Format::Template => {
+                if let Some(ref template) = opt.format {
+                    template.render(opt, self, &mut out)?;
+                    return end_record(opt, out);
+                }
+            }
+            Format::Columns | Format::Tabs => (),
+        }
+
+        self.write_columns(opt, width, &self.columns(opt), highlight, out)
+    }
+
+    /// Print the change in each enabled column since `base`, such as `+42` or
+    /// `-3`, for `--baseline`.  Only columnar formats are supported.
+    pub fn print_delta<W: Write>(
+        &self,
+        opt: &Opt,
+        width: usize,
+        base: &Counts,
+        out: W,
+    ) -> io::Result<()> {
+        self.write_columns(opt, width, &self.delta_columns(opt, base), &[], out)
+    }
+
+    fn write_columns<W: Write>(
+        &self,
+        opt: &Opt,
+        width: usize,
+        columns: &[String],
+        highlight: &[u64],
+        mut out: W,
+    ) -> io::Result<()> {
+        let format = Format::from(opt);
+
+        // A lone count from a stream is most useful bare, as with `wc -l < file`
+        if self.path.is_none() && columns.len() == 1 && !opt.header && !opt.wc_compat {
+            write!(&mut out, "{}", columns[0])?;
+            return end_record(opt, out);
+        }
+
+        if format == Format::Tabs {
+            let mut sep = "";
+            for column in columns {
+                write!(&mut out, "{}{}", sep, column)?;
+                sep = "\t";
+            }
+
+            if let Some(ref path) = self.path {
+                write!(&mut out, "{}", sep)?;
+                write_path(opt, path, &mut out)?;
+            }
+        } else if opt.color == ColorChoice::Always {
+            let values = self
+                .metrics(opt)
+                .into_iter()
+                .filter(|(_, enabled, _)| *enabled)
+                .map(|(_, _, value)| value);
+
+            for (i, (column, value)) in columns.iter().zip(values).enumerate() {
+                let column = format!("{:>1$}", column, width);
+                if value > 0 && highlight.get(i) == Some(&value) {
+                    write!(&mut out, " {}", Colour::Yellow.bold().paint(column))?;
+                } else {
+                    write!(&mut out, " {}", column)?;
+                }
+            }
+
+            if let Some(ref path) = self.path {
+                let mut name = vec![];
+                write_path(opt, path, &mut name)?;
+                write!(&mut out, " ")?;
+                Colour::Cyan.paint(name).write_to(&mut out)?;
+            }
+        } else {
+            for column in columns {
+                write!(&mut out, " {:>1$}", column, width)?;
+            }
+
+            if let Some(ref path) = self.path {
+                write!(&mut out, " ")?;
+                write_path(opt, path, &mut out)?;
+            }
+        }
+
+        end_record(opt, out)
+    }
+
+    /// The labels of the enabled columns, in display order
+    pub fn labels(opt: &Opt) -> Vec<&'static str> {
+        Self::default()
+            .metrics(opt)
+            .into_iter()
+            .filter(|(_, enabled, _)| *enabled)
+            .map(|(name, _, _)| name)
+            .collect()
+    }
+
+    /// Print a row of column labels, aligned as `print_width` would align counts
+    pub fn print_header<W: Write>(opt: &Opt, width: usize, mut out: W) -> io::Result<()> {
+        if Format::from(opt) == Format::Tabs {
+            for label in Self::labels(opt) {
+                write!(&mut out, "{}\t", label)?;
+            }
+            write!(&mut out, "path")?;
+        } else {
+            for label in Self::labels(opt) {
+                write!(&mut out, " {:>1$}", label, width)?;
+            }
+            write!(&mut out, " path")?;
+        }
+
+        end_record(opt, out)
+    }
+
+    /// The metrics given a column by `print_csv`: the fixed lines, words,
+    /// chars, bytes and longest_line columns, empty unless enabled, followed by
+    /// any other metric `opt` enables
+    pub fn csv_columns(&self, opt: &Opt) -> Vec<(&'static str, bool, u64)> {
+        self.metrics(opt)
+            .into_iter()
+            .enumerate()
+            .filter(|(i, (_, enabled, _))| *i < CSV_FIXED_COLUMNS || *enabled)
+            .map(|(_, metric)| metric)
+            .collect()
     }
 
-    pub fn print<W: Write>(&self, opt: &Opt, mut out: W) -> io::Result<()> {
-        if opt.lines {
-            write!(&mut out, " {:>7}", self.lines)?;
+    /// Every metric in display order, paired with whether `opt` enables it
+    pub fn metrics(&self, opt: &Opt) -> Vec<(&'static str, bool, u64)> {
+        self.tagged_metrics(opt)
+            .into_iter()
+            .map(|(name, enabled, value, _)| (name, enabled, value))
+            .collect()
+    }
+
+    // Like `metrics`, also marking which are derived from other counts rather
+    // than counted themselves
+    fn tagged_metrics(&self, opt: &Opt) -> Vec<(&'static str, bool, u64, bool)> {
+        let mut metrics = vec![
+            ("lines", opt.lines, self.lines, false),
+            ("words", opt.words, self.words, false),
+            ("chars", opt.chars, self.chars, false),
+            ("bytes", opt.bytes && !opt.chars, self.bytes, false),
+            ("longest_line", opt.longest_line, self.longest_line, false),
+            (
+                "longest_line_at",
+                opt.longest_line_number,
+                self.longest_line_at,
+                false,
+            ),
+            (
+                "shortest_line",
+                opt.shortest_line,
+                self.shortest_line.unwrap_or(0),
+                false,
+            ),
+            (
+                "avg_line_length",
+                opt.avg_line_length,
+                self.avg_line_length(opt),
+                true,
+            ),
+            ("longest_word", opt.longest_word, self.longest_word, false),
+            (
+                "max_words_per_line",
+                opt.max_words_per_line,
+                self.max_words_per_line,
+                false,
+            ),
+            ("unique_lines", opt.unique, self.unique_lines, false),
+            ("graphemes", opt.graphemes, self.graphemes, false),
+            ("blank_lines", opt.blank_lines, self.blank_lines, false),
+            ("paragraphs", opt.paragraphs, self.paragraphs, false),
+            ("records", opt.record_bytes.is_some(), self.records, false),
+        ];
+
+        // A byte_matches column for each --count-byte, so there's always at least one
+        let byte_columns = opt.count_byte.len().max(self.byte_matches.len()).max(1);
+        for i in 0..byte_columns {
+            metrics.push((
+                "byte_matches",
+                i < opt.count_byte.len(),
+                self.byte_matches.get(i).copied().unwrap_or(0),
+                false,
+            ));
         }
 
-        if opt.words {
-            write!(&mut out, " {:>7}", self.words)?;
+        metrics
+    }
+
+    /// Mean bytes, or characters with `chars`, per line, rounded to the nearest whole
+    pub fn avg_line_length(&self, opt: &Opt) -> u64 {
+        let len = if opt.chars { self.chars } else { self.bytes };
+        (len + self.lines / 2).checked_div(self.lines).unwrap_or(0)
+    }
+
+    /// Look up a metric by name, regardless of whether it was counted
+    pub fn metric(&self, name: &str) -> Option<u64> {
+        self.metrics(&Opt::default())
+            .into_iter()
+            .find(|(metric, _, _)| *metric == name)
+            .map(|(_, _, value)| value)
+    }
+
+    /// The enabled numeric columns, formatted in display order
+    fn columns(&self, opt: &Opt) -> Vec<String> {
+        self.metrics(opt)
+            .into_iter()
+            .filter(|(_, enabled, _)| *enabled)
+            .map(|(name, _, value)| match name {
+                "bytes" if opt.human => human_bytes(value),
+                "longest_line_at" => match self.longest_line_path {
+                    Some(ref path) => format!("{}:{}", path.display(), value),
+                    None => value.to_string(),
+                },
+                _ => value.to_string(),
+            })
+            .collect()
+    }
+
+    /// The enabled numeric columns as signed differences from `base`
+    fn delta_columns(&self, opt: &Opt, base: &Counts) -> Vec<String> {
+        self.metrics(opt)
+            .into_iter()
+            .zip(base.metrics(opt))
+            .filter(|((_, enabled, _), _)| *enabled)
+            .map(|((_, _, value), (_, _, was))| {
+                let delta = i128::from(value) - i128::from(was);
+                if delta > 0 {
+                    format!("+{}", delta)
+                } else {
+                    delta.to_string()
+                }
+            })
+            .collect()
+    }
+
+    /// The width needed to display the widest enabled column
+    pub fn width(&self, opt: &Opt) -> usize {
+        self.columns(opt).iter().map(String::len).max().unwrap_or(1)
+    }
+
+    /// Like `width`, but for the differences `print_delta` would display
+    pub fn delta_width(&self, opt: &Opt, base: &Counts) -> usize {
+        self.delta_columns(opt, base)
+            .iter()
+            .map(String::len)
+            .max()
+            .unwrap_or(1)
+    }
+
+    /// Set a metric by the name `metrics` gives it, as when reading back saved
+    /// output, returning false if the name isn't known.  Each `byte_matches` adds
+    /// another column.
+    pub fn set_metric(&mut self, name: &str, value: u64) -> bool {
+        match name {
+            "lines" => self.lines = value,
+            "words" => self.words = value,
+            "chars" => self.chars = value,
+            "bytes" => self.bytes = value,
+            "longest_line" => self.longest_line = value,
+            "longest_line_at" => self.longest_line_at = value,
+            "shortest_line" => self.shortest_line = Some(value),
+            // Derived from lines and bytes or chars
+            "avg_line_length" => (),
+            "longest_word" => self.longest_word = value,
+            "max_words_per_line" => self.max_words_per_line = value,
+            "unique_lines" => self.unique_lines = value,
+            "graphemes" => self.graphemes = value,
+            "blank_lines" => self.blank_lines = value,
+            "paragraphs" => self.paragraphs = value,
+            "records" => self.records = value,
+            "byte_matches" => self.byte_matches.push(value),
+            _ => return false,
         }
+        true
+    }
 
-        if opt.chars {
-            write!(&mut out, " {:>7}", self.chars)?;
-        } else if opt.bytes {
-            write!(&mut out, " {:>7}", self.bytes)?;
+    /// Write a CSV record with every metric in display order followed by the
+    /// path, leaving disabled metrics empty.
+    pub fn print_csv<W: Write>(&self, opt: &Opt, mut out: W) -> io::Result<()> {
+        for (_, enabled, value) in self.csv_columns(opt) {
+            if enabled {
+                write!(&mut out, "{}", value)?;
+            }
+            write!(&mut out, ",")?;
         }
 
-        if opt.longest_line {
-            write!(&mut out, " {:>7}", self.longest_line)?;
+        if let Some(ref path) = self.path {
+            let path = path.to_string_lossy();
+            if path.contains(&[',', '"', '\r', '\n'][..]) {
+                write!(&mut out, "\"{}\"", path.replace('"', "\"\""))?;
+            } else {
+                write!(&mut out, "{}", path)?;
+            }
         }
 
+        end_record(opt, out)
+    }
+
+    /// Write the enabled counts as a single JSON object, without a trailing newline
+    pub fn print_json<W: Write>(&self, opt: &Opt, out: W) -> io::Result<()> {
+        self.print_json_with(opt, &[], out)
+    }
+
+    /// Like `print_json`, with `extra` fields following the metrics, such as
+    /// the number of files in a total
+    pub fn print_json_with<W: Write>(
+        &self,
+        opt: &Opt,
+        extra: &[(&str, u64)],
+        mut out: W,
+    ) -> io::Result<()> {
+        let mut sep = "";
+        write!(&mut out, "{{")?;
+
         if let Some(ref path) = self.path {
-            write!(&mut out, " {}", path.display())?;
+            write!(&mut out, "\"path\":")?;
+            write_json_str(&mut out, &path.to_string_lossy())?;
+            sep = ",";
+        }
+
+        for (name, _, value) in self.metrics(opt).iter().filter(|(_, enabled, _)| *enabled) {
+            // Listed together below, rather than repeating the key
+            if *name != "byte_matches" {
+                write!(&mut out, "{}\"{}\":{}", sep, name, value)?;
+                sep = ",";
+            }
+        }
+
+        if !opt.count_byte.is_empty() {
+            write!(&mut out, "{}\"byte_matches\":[", sep)?;
+            for (i, _) in opt.count_byte.iter().enumerate() {
+                let comma = if i > 0 { "," } else { "" };
+                write!(
+                    &mut out,
+                    "{}{}",
+                    comma,
+                    self.byte_matches.get(i).unwrap_or(&0)
+                )?;
+            }
+            write!(&mut out, "]")?;
+            sep = ",";
+        }
+
+        if let (true, Some(path)) = (opt.longest_line_number, &self.longest_line_path) {
+            write!(&mut out, "{}\"longest_line_path\":", sep)?;
+            write_json_str(&mut out, &path.to_string_lossy())?;
+            sep = ",";
+        }
+
+        for (name, value) in extra {
+            write!(&mut out, "{}", sep)?;
+            write_json_str(&mut out, name)?;
+            write!(&mut out, ":{}", value)?;
+            sep = ",";
+        }
+
+        write!(&mut out, "}}")
+    }
+}
+
+#[test]
+fn test_print_highlighted() {
+    let opt = Opt {
+        lines: true,
+        bytes: true,
+        color: ColorChoice::Always,
+        ..Opt::default()
+    };
+    let mut c = Counts::new("a");
+    c.lines = 2;
+    c.bytes = 6;
+
+    let mut out = vec![];
+    c.print_highlighted(&opt, 1, &[2, 9], &mut out).unwrap();
+    assert_eq!(out, b" \x1b[1;33m2\x1b[0m 6 \x1b[36ma\x1b[0m\n");
+
+    let mut plain = vec![];
+    c.print_highlighted(
+        &Opt {
+            color: ColorChoice::Never,
+            ..opt
+        },
+        1,
+        &[2, 9],
+        &mut plain,
+    )
+    .unwrap();
+    assert_eq!(plain, b" 2 6 a\n");
+}
+
+/// Scale a byte count to base-1024 units with one decimal place, e.g. 1.2M
+pub fn human_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 6] = ["K", "M", "G", "T", "P", "E"];
+
+    if bytes < 1024 {
+        return bytes.to_string();
+    }
+
+    // Anything that would round up to 1024.0 goes to the next unit instead
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1023.95 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    format!("{:.1}{}", value, UNITS[unit])
+}
+
+#[test]
+fn test_avg_line_length() {
+    let mut c = Counts::default();
+    assert_eq!(c.avg_line_length(&Opt::default()), 0);
+
+    c.lines = 4;
+    c.bytes = 10;
+    c.chars = 6;
+    assert_eq!(c.avg_line_length(&Opt::default()), 3);
+
+    let opt = Opt {
+        chars: true,
+        ..Opt::default()
+    };
+    assert_eq!(c.avg_line_length(&opt), 2);
+}
+
+#[test]
+fn test_human_bytes() {
+    assert_eq!(human_bytes(0), "0");
+    assert_eq!(human_bytes(1023), "1023");
+    assert_eq!(human_bytes(1024), "1.0K");
+    assert_eq!(human_bytes(1258291), "1.2M");
+
+    // Values just under a unit round into it
+    assert_eq!(human_bytes(1048524), "1023.9K");
+    assert_eq!(human_bytes(1048525), "1.0M");
+    assert_eq!(human_bytes(1048575), "1.0M");
+    assert_eq!(human_bytes(1048576), "1.0M");
+    assert_eq!(human_bytes((1 << 30) - 1), "1.0G");
+    assert_eq!(human_bytes(u64::MAX), "16.0E");
+}
+
+/// Print in-progress counts to stderr, or stdout with --siginfo-stdout, as
+/// requested by a signal or --progress-interval
+fn print_progress(count: &Counts, opt: &Opt) {
+    if opt.siginfo_stdout {
+        let _ = count.print(opt, io::stdout().lock());
+    } else {
+        let _ = count.print(opt, io::stderr().lock());
+    }
+}
+
+/// Between reads, print progress if asked to, and stop with `Interrupted` once
+/// Ctrl-C has been pressed
+fn check_progress(count: &Counts, opt: &Opt) -> io::Result<()> {
+    if siginfo::check_signal() || siginfo::check_interval(opt.progress_interval) {
+        print_progress(count, opt);
+    }
+
+    if siginfo::interrupted() {
+        return Err(Interrupted.into());
+    }
+    Ok(())
+}
+
+/// Write a path as its raw bytes with `raw_paths` or `null` on Unix, so any
+/// name can be read back exactly, or lossily as UTF-8 otherwise.
+pub(crate) fn write_path<W: Write>(opt: &Opt, path: &Path, mut out: W) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+
+        if opt.raw_paths || opt.null {
+            return out.write_all(path.as_os_str().as_bytes());
         }
+    }
+
+    #[cfg(not(unix))]
+    let _ = opt;
+
+    write!(out, "{}", path.display())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_write_path() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
 
-        writeln!(&mut out)
+    let path = Path::new(OsStr::from_bytes(b"a\xFFb"));
+
+    let mut out = vec![];
+    write_path(&Opt::default(), path, &mut out).unwrap();
+    assert_eq!(out, "a\u{FFFD}b".as_bytes());
+
+    let opt = Opt {
+        raw_paths: true,
+        ..Opt::default()
+    };
+    let mut out = vec![];
+    write_path(&opt, path, &mut out).unwrap();
+    assert_eq!(out, b"a\xFFb");
+}
+
+pub(crate) fn end_record<W: Write>(opt: &Opt, mut out: W) -> io::Result<()> {
+    out.write_all(if opt.null { b"\0" } else { b"\n" })
+}
+
+pub(crate) fn write_json_str<W: Write>(mut out: W, s: &str) -> io::Result<()> {
+    write!(&mut out, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(&mut out, "\\\"")?,
+            '\\' => write!(&mut out, "\\\\")?,
+            '\n' => write!(&mut out, "\\n")?,
+            '\r' => write!(&mut out, "\\r")?,
+            '\t' => write!(&mut out, "\\t")?,
+            c if (c as u32) < 0x20 => write!(&mut out, "\\u{:04x}", c as u32)?,
+            c => write!(&mut out, "{}", c)?,
+        }
     }
+    write!(&mut out, "\"")
 }
 
 impl Capability {
     fn is_compatible(&self, opt: &Opt) -> bool {
-        (!opt.lines || self.lines)
-            && (!opt.bytes || self.bytes)
-            && (!opt.chars || self.chars)
-            && (!opt.words || (self.words && self.chars == opt.chars))
-            && (!opt.longest_line || (self.longest_line && self.chars == opt.chars))
+        self.missing(opt).is_empty()
+    }
+
+    // The requirements of `opt` this can't meet
+    fn missing(&self, opt: &Opt) -> Vec<&'static str> {
+        // With --ascii, bytes are characters
+        let unit = self.chars == opt.chars || self.any_unit || opt.ascii;
+
+        let requirements = [
+            ("lines", !(opt.lines || opt.avg_line_length) || self.lines),
+            ("bytes", !opt.bytes || self.bytes),
+            ("chars", !opt.chars || self.chars || opt.ascii),
+            ("graphemes", !opt.graphemes || self.graphemes),
+            ("blank_lines", !opt.blank_lines || self.blank_lines),
+            ("paragraphs", !opt.paragraphs || self.paragraphs),
+            ("longest_word", !opt.longest_word || self.longest_word),
+            (
+                "max_words_per_line",
+                !opt.max_words_per_line || self.max_words_per_line,
+            ),
+            (
+                "word_separators",
+                opt.word_separators.is_none()
+                    || !(opt.words || opt.longest_word)
+                    || self.word_separators,
+            ),
+            (
+                "words",
+                !opt.words || (self.words && unit && self.unicode_words == opt.unicode_words),
+            ),
+            (
+                "longest_line",
+                !opt.longest_line || (self.longest_line && unit),
+            ),
+            (
+                "shortest_line",
+                !opt.shortest_line || (self.longest_line && unit),
+            ),
+        ];
+
+        requirements
+            .iter()
+            .filter(|(_, met)| !met)
+            .map(|(name, _)| *name)
+            .collect()
+    }
+
+    /// Whether this counts `metric`, as named by `Counts::metrics`, even if it
+    /// wasn't asked for
+    pub fn counts(&self, metric: &str) -> bool {
+        self.flags().contains(&metric)
+    }
+
+    // What this can count, for --explain
+    fn flags(&self) -> Vec<&'static str> {
+        let flags = [
+            ("lines", self.lines),
+            ("words", self.words),
+            ("bytes", self.bytes),
+            ("chars", self.chars),
+            ("longest_line", self.longest_line),
+            ("graphemes", self.graphemes),
+            ("blank_lines", self.blank_lines),
+            ("paragraphs", self.paragraphs),
+            ("longest_word", self.longest_word),
+            ("max_words_per_line", self.max_words_per_line),
+            ("unicode_words", self.unicode_words),
+            ("word_separators", self.word_separators),
+            ("any_unit", self.any_unit),
+        ];
+
+        flags
+            .iter()
+            .filter(|(_, has)| *has)
+            .map(|(name, _)| *name)
+            .collect()
+    }
+}
+
+/// No counting strategy can provide every metric an `Opt` asks for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnsupportedOptions;
+
+impl fmt::Display for UnsupportedOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unsupported combination of options")
+    }
+}
+
+impl std::error::Error for UnsupportedOptions {}
+
+impl From<UnsupportedOptions> for io::Error {
+    fn from(e: UnsupportedOptions) -> Self {
+        io::Error::new(io::ErrorKind::InvalidInput, e)
     }
 }
 
+/// A file was left uncounted by `skip_binary`, as its first few kilobytes
+/// contain a NUL byte.
+///
+/// Returned from `count_file` wrapped in an `io::Error` of kind `InvalidData`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BinaryFile;
+
+impl fmt::Display for BinaryFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "binary file skipped")
+    }
+}
+
+impl std::error::Error for BinaryFile {}
+
+impl From<BinaryFile> for io::Error {
+    fn from(e: BinaryFile) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, e)
+    }
+}
+
+impl BinaryFile {
+    /// Whether an error is a skipped binary file, rather than a real failure
+    pub fn is(e: &io::Error) -> bool {
+        e.get_ref().is_some_and(|e| e.is::<BinaryFile>())
+    }
+}
+
+/// Counting stopped part way by Ctrl-C
+#[derive(Debug)]
+pub struct Interrupted;
+
+impl fmt::Display for Interrupted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "interrupted")
+    }
+}
+
+impl std::error::Error for Interrupted {}
+
+impl From<Interrupted> for io::Error {
+    fn from(e: Interrupted) -> Self {
+        io::Error::other(e)
+    }
+}
+
+impl Interrupted {
+    /// Whether an error is counting cut short by Ctrl-C
+    pub fn is(e: &io::Error) -> bool {
+        e.get_ref().is_some_and(|e| e.is::<Interrupted>())
+    }
+}
+
+// How much of a file to check for NUL bytes with --skip-binary, as grep does
+const BINARY_SAMPLE: u64 = 8 * 1024;
+
+// Whether the start of a file looks like binary rather than text
+fn is_binary(path: &Path) -> io::Result<bool> {
+    let mut sample = Vec::with_capacity(BINARY_SAMPLE as usize);
+    File::open(path)?
+        .take(BINARY_SAMPLE)
+        .read_to_end(&mut sample)?;
+    Ok(memchr::memchr(0, &sample).is_some())
+}
+
 macro_rules! counter_strategies {
     ($($name:ident,)+) => {
         #[derive(Debug, Clone, Copy)]
@@ -125,18 +1011,25 @@ macro_rules! counter_strategies {
             $($name,)+
         }
 
-        impl From<&Opt> for Strategy {
-            fn from(opt: &Opt) -> Self {
-                let strategies = [
-                    $((Strategy::$name, $name.capabilities()),)+
-                ];
+        impl TryFrom<&Opt> for Strategy {
+            type Error = UnsupportedOptions;
+
+            fn try_from(opt: &Opt) -> Result<Self, Self::Error> {
+                if let Some(strategy) = opt.strategy {
+                    return if strategy.capabilities().is_compatible(opt) {
+                        Ok(strategy)
+                    } else {
+                        Err(UnsupportedOptions)
+                    };
+                }
 
-                strategies
+                Strategy::ALL
                     .iter()
+                    .map(|strat| (strat, strat.capabilities()))
                     .filter(|(_, cap)| cap.is_compatible(&opt))
                     .min_by(|(_, a), (_, b)| a.rank.cmp(&b.rank))
                     .map(|(strat, _)| *strat)
-                    .expect("[BUG] Unable to find a suitable implementation")
+                    .ok_or(UnsupportedOptions)
             }
         }
 
@@ -147,51 +1040,643 @@ macro_rules! counter_strategies {
                 }
             }
 
-            fn count<R: Read>(&self, r: R, mut count: &mut Counts, opt: &Opt) -> io::Result<()> {
-                match self {
-                    $(Strategy::$name => $name.count(r, &mut count, &opt),)+
+            fn count<R: Read>(&self, mut r: R, count: &mut Counts, opt: &Opt) -> io::Result<()> {
+                match opt.bytes_range {
+                    // Readers in general can't seek, so read up to the start
+                    Some(ref range) => {
+                        io::copy(&mut r.by_ref().take(range.start), &mut io::sink())?;
+                        self.count_all(r.take(range.limit()), count, opt)
+                    }
+                    None => self.count_all(r, count, opt),
                 }
             }
 
-            fn count_file<F: AsRef<Path>>(&self, path: F, opt: &Opt) -> io::Result<Counts> {
-                match self {
-                    $(Strategy::$name => $name.count_file(path, &opt),)+
+            fn count_slice(&self, mut buf: &[u8], count: &mut Counts, opt: &Opt) -> io::Result<()> {
+                if opt.encoding.is_some() || opt.decompress || filters_lines(opt) {
+                    return self.count(buf, count, opt);
                 }
-            }
-        }
-    }
-}
 
-counter_strategies! {
-    BytesOnly,
-    LinesOnly,
-    CharsOnly,
-    LinesLongest,
-    WordsLinesLongest,
-    CharsLinesLongest,
-    CharsWordsLinesLongest,
-}
+                if let Some(ref range) = opt.bytes_range {
+                    let offset = |n: u64| usize::try_from(n).unwrap_or(usize::MAX).min(buf.len());
+                    let start = offset(range.start);
+                    let end = offset(range.start.saturating_add(range.limit()));
+                    buf = &buf[start..end];
+                }
 
-pub trait Counter {
-    fn capabilities(&self) -> Capability;
+                count.byte_matches = opt
+                    .count_byte
+                    .iter()
+                    .map(|b| bytecount::count(buf, *b) as u64)
+                    .collect();
 
-    fn count<R: Read>(&self, r: R, count: &mut Counts, opt: &Opt) -> io::Result<()>;
+                if opt.skip_bom && buf.starts_with(UTF8_BOM) {
+                    buf = &buf[UTF8_BOM.len()..];
+                    count.bytes += UTF8_BOM.len() as u64;
+                }
 
-    fn count_file<F: AsRef<Path>>(&self, path: F, opt: &Opt) -> io::Result<Counts> {
-        let path = path.as_ref();
-        let mut count = Counts::new(path);
+                if opt.ascii {
+                    if let Some(pos) = buf.iter().position(|b| !b.is_ascii()) {
+                        return Err(non_ascii(buf[pos], pos as u64));
+                    }
+                }
 
-        open_file(&path).and_then(|fd| self.count(fd, &mut count, &opt))?;
-        Ok(count)
-    }
-}
+                match self {
+                    $(Strategy::$name => $name.count_slice(buf, count, opt)?,)+
+                }
 
-macro_rules! fn_count {
-    ($counter:expr) => {
-        fn count<R: Read>(&self, r: R, count: &mut Counts, opt: &Opt) -> io::Result<()> {
-            let mut reader = BufReader::with_capacity(READ_SIZE, r);
+                if opt.ascii && opt.chars && !self.capabilities().chars {
+                    count.chars += buf.len() as u64;
+                }
+
+                count_records(count, opt);
+                Ok(())
+            }
+
+            fn count_file<F: AsRef<Path>>(&self, path: F, opt: &Opt) -> io::Result<Counts> {
+                let path = path.as_ref();
+
+                // Input transformations live in our count and count_slice
+                let ret = if opt.skip_binary && is_binary(path).unwrap_or(false) {
+                    Err(BinaryFile.into())
+                } else if let Some(ref range) = opt.bytes_range {
+                    self.count_file_range(path, range, opt)
+                } else if opt.encoding.is_some()
+                    || opt.skip_bom
+                    || opt.ascii
+                    || opt.decompress
+                    || filters_lines(opt)
+                    || !opt.count_byte.is_empty()
+                {
+                    count_file_with(self, path, opt)
+                } else {
+                    match self {
+                        $(Strategy::$name => $name.count_file(path, &opt),)+
+                    }
+                };
+
+                match ret {
+                    Ok(mut count) => {
+                        count_records(&mut count, opt);
+                        Ok(count)
+                    }
+                    Err(ref e) if opt.ignore_missing && e.kind() == io::ErrorKind::NotFound => {
+                        Ok(Counts::new(path))
+                    }
+                    Err(e) => Err(e),
+                }
+            }
+        }
+
+        impl Strategy {
+            /// Every strategy, in the order they're declared
+            pub const ALL: &'static [Strategy] = &[$(Strategy::$name,)+];
+
+            /// The metrics and options asked for by `opt` which this can't count
+            pub fn lacks(&self, opt: &Opt) -> Vec<&'static str> {
+                self.capabilities().missing(opt)
+            }
+
+            // Count every byte of a reader, besides the start of --bytes-range
+            fn count_all<R: Read>(&self, r: R, count: &mut Counts, opt: &Opt) -> io::Result<()> {
+                if opt.decompress {
+                    self.count_tallied(decompress(r)?, count, opt)?;
+                } else {
+                    self.count_tallied(r, count, opt)?;
+                }
+
+                count_records(count, opt);
+                Ok(())
+            }
+
+            // Count only part of a file, seeking to the start of it
+            fn count_file_range(&self, path: &Path, range: &ByteRange, opt: &Opt) -> io::Result<Counts> {
+                let mut count = Counts::new(path);
+                let mut file = open_file(path)?;
+                file.seek(SeekFrom::Start(range.start))?;
+
+                self.count_all(file.take(range.limit()), &mut count, opt)?;
+                Ok(count)
+            }
+
+            // Tally --count-byte over the input, before any character decoding
+            fn count_tallied<R: Read>(&self, r: R, count: &mut Counts, opt: &Opt) -> io::Result<()> {
+                if !opt.count_byte.is_empty() {
+                    let mut reader = ByteMatchReader::new(r, &opt.count_byte);
+                    self.count_input(&mut reader, count, opt)?;
+                    count.byte_matches = reader.matches;
+                    Ok(())
+                } else {
+                    self.count_input(r, count, opt)
+                }
+            }
+
+            // Count through any decoding and line filtering the options ask for
+            fn count_input<R: Read>(&self, r: R, count: &mut Counts, opt: &Opt) -> io::Result<()> {
+                if let Some(encoding) = opt.encoding.filter(|e| *e != encoding_rs::UTF_8) {
+                    match self {
+                        $(Strategy::$name => count_decoded(&$name, r, encoding, count, opt)?,)+
+                    }
+                } else if opt.skip_bom {
+                    let (skipped, r) = skip_bom(r)?;
+                    count.bytes += skipped;
+
+                    match self {
+                        $(Strategy::$name => count_ascii(&$name, r, count, opt)?,)+
+                    }
+                } else {
+                    match self {
+                        $(Strategy::$name => count_ascii(&$name, r, count, opt)?,)+
+                    }
+                }
+
+                Ok(())
+            }
+        }
+    }
+}
+
+counter_strategies! {
+    BytesOnly,
+    LinesOnly,
+    CharsOnly,
+    LinesLongest,
+    WordsLinesLongest,
+    CharsLinesLongest,
+    CharsWordsLinesLongest,
+    UnicodeWordsCharsLinesLongest,
+    GraphemesCharsWordsLinesLongest,
+}
+
+impl std::str::FromStr for Strategy {
+    type Err = String;
+
+    /// A strategy by the name it's listed under by `--explain`, ignoring case
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Strategy::ALL
+            .iter()
+            .find(|strategy| format!("{:?}", strategy).eq_ignore_ascii_case(s))
+            .copied()
+            .ok_or_else(|| format!("unknown strategy {}", s))
+    }
+}
+
+impl Strategy {
+    /// Describe the choice of strategy for `opt`, for `--explain`: the lowest
+    /// ranked of those which can count everything asked for, with what each
+    /// can count and what it lacks.
+    pub fn explain<W: Write>(opt: &Opt, mut out: W) -> io::Result<()> {
+        match Strategy::try_from(opt) {
+            Ok(strategy) if opt.strategy.is_some() => {
+                writeln!(&mut out, "chosen: {:?}, forced by --strategy", strategy)?
+            }
+            Ok(strategy) => writeln!(&mut out, "chosen: {:?}", strategy)?,
+            Err(_) if opt.strategy.is_some() => writeln!(
+                &mut out,
+                "chosen: none, as --strategy can't count everything"
+            )?,
+            Err(e) => writeln!(&mut out, "chosen: none, {}", e)?,
+        }
+
+        for strategy in Strategy::ALL {
+            let cap = strategy.capabilities();
+            let missing = strategy.lacks(opt);
+
+            writeln!(&mut out, "\n{:?}, rank {}", strategy, cap.rank)?;
+            writeln!(&mut out, "  counts: {}", cap.flags().join(" "))?;
+            if missing.is_empty() {
+                writeln!(&mut out, "  compatible")?;
+            } else {
+                writeln!(&mut out, "  lacks: {}", missing.join(" "))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Whether `count_parallel` can split a stream between threads: only counts
+    /// of lines and bytes merge simply, without any line filtering or decoding.
+    pub fn is_parallel(&self, opt: &Opt) -> bool {
+        matches!(self, Strategy::BytesOnly | Strategy::LinesOnly)
+            && opt.encoding.is_none()
+            && !opt.decompress
+            && !opt.skip_bom
+            && !opt.ascii
+            && !opt.count_partial_line
+            && opt.bytes_range.is_none()
+            && !filters_lines(opt)
+    }
+
+    /// Count a single stream, such as stdin, on up to `threads` threads by
+    /// reading it in chunks that are counted separately and summed.
+    ///
+    /// Falls back to `count` on this thread unless `is_parallel`.  Words would
+    /// need carrying across each chunk edge, since one may be split between two,
+    /// as would the length of a line.
+    pub fn count_parallel<R: Read>(
+        &self,
+        r: R,
+        count: &mut Counts,
+        opt: &Opt,
+        threads: usize,
+    ) -> io::Result<()> {
+        if threads < 2 || !self.is_parallel(opt) {
+            return self.count(r, count, opt);
+        }
+
+        // read_to_end retries reads interrupted by Ctrl-C otherwise
+        let mut r = InterruptReader::new(r);
+
+        let (chunk_tx, chunk_rx) = crossbeam_channel::bounded::<Vec<u8>>(threads * 2);
+
+        let (counted, read) = crossbeam_utils::thread::scope(|scope| {
+            let workers: Vec<_> = (0..threads)
+                .map(|_| {
+                    let chunk_rx = chunk_rx.clone();
+                    scope.spawn(move |_| -> io::Result<Counts> {
+                        // count_slice expects a whole input, so each chunk gets its own counts
+                        let mut count = Counts::default();
+                        for chunk in chunk_rx {
+                            let mut counted = Counts::default();
+                            self.count_slice(&chunk, &mut counted, opt)?;
+                            count.add(&counted);
+                        }
+                        Ok(count)
+                    })
+                })
+                .collect();
+            drop(chunk_rx);
+
+            let read = loop {
+                if siginfo::interrupted() {
+                    break Err(Interrupted.into());
+                }
+
+                let mut chunk = Vec::with_capacity(PARALLEL_CHUNK);
+                match r
+                    .by_ref()
+                    .take(PARALLEL_CHUNK as u64)
+                    .read_to_end(&mut chunk)
+                {
+                    Ok(0) => break Ok(()),
+                    // Workers only hang up early when they fail, which join reports
+                    Ok(_) => {
+                        if chunk_tx.send(chunk).is_err() {
+                            break Ok(());
+                        }
+                    }
+                    Err(e) => {
+                        // What was read before Ctrl-C still goes towards the total
+                        if Interrupted::is(&e) {
+                            let _ = chunk_tx.send(chunk);
+                        }
+                        break Err(e);
+                    }
+                }
+            };
+            drop(chunk_tx);
+
+            let mut counts = vec![];
+            for worker in workers {
+                counts.push(worker.join().expect("counting thread")?);
+            }
+            io::Result::Ok((counts, read))
+        })
+        .expect("counting threads")?;
+
+        for c in &counted {
+            count.add(c);
+        }
+        read?;
+        count_records(count, opt);
+        Ok(())
+    }
+
+    /// Count a stream while copying every byte of it to `out` as it's read, for
+    /// `--tee`, flushing `out` at the end.
+    ///
+    /// The whole stream is copied even if counting stops short of its end, as
+    /// with `bytes_range` or `max_lines`, or fails part way, as with `ascii`.
+    /// Only Ctrl-C stops the copy early.
+    pub fn count_tee<R: Read, W: Write>(
+        &self,
+        r: R,
+        out: W,
+        count: &mut Counts,
+        opt: &Opt,
+    ) -> io::Result<()> {
+        let mut reader = TeeReader::new(r, out);
+        let counted = self.count(&mut reader, count, opt);
+        let copied = match counted {
+            Err(ref e) if Interrupted::is(e) => Ok(()),
+            _ => io::copy(&mut reader, &mut io::sink()).map(drop),
+        };
+        let flushed = reader.writer().flush();
+        counted.and(copied).and(flushed)
+    }
+}
+
+pub trait Counter {
+    fn capabilities(&self) -> Capability;
+
+    fn count<R: Read>(&self, r: R, count: &mut Counts, opt: &Opt) -> io::Result<()>;
+
+    // Count an entire input already in memory
+    fn count_slice(&self, buf: &[u8], count: &mut Counts, opt: &Opt) -> io::Result<()> {
+        self.count(buf, count, opt)
+    }
+
+    fn count_file<F: AsRef<Path>>(&self, path: F, opt: &Opt) -> io::Result<Counts> {
+        count_file_with(self, path, opt)
+    }
+}
+
+fn count_file_with<C: Counter + ?Sized, F: AsRef<Path>>(
+    counter: &C,
+    path: F,
+    opt: &Opt,
+) -> io::Result<Counts> {
+    let path = path.as_ref();
+    let mut count = Counts::new(path);
+    let fd = open_file(path)?;
+
+    if opt.mmap {
+        if let Some(map) = map_file(&fd) {
+            counter.count_slice(&map, &mut count, opt)?;
+            return Ok(count);
+        }
+    }
+
+    counter.count(fd, &mut count, opt)?;
+    Ok(count)
+}
+
+const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+
+// Read past any leading UTF-8 byte order mark, returning the number of bytes
+// skipped and a reader over the rest of the input
+fn skip_bom<R: Read>(mut r: R) -> io::Result<(u64, impl Read)> {
+    let mut prefix = Vec::with_capacity(UTF8_BOM.len());
+    r.by_ref()
+        .take(UTF8_BOM.len() as u64)
+        .read_to_end(&mut prefix)?;
+
+    let skipped = if prefix == UTF8_BOM {
+        prefix.clear();
+        UTF8_BOM.len() as u64
+    } else {
+        0
+    };
+
+    Ok((skipped, Cursor::new(prefix).chain(r)))
+}
+
+// Count input in another encoding by transcoding it to UTF-8, while keeping
+// the original byte count
+fn count_decoded<C: Counter, R: Read>(
+    counter: &C,
+    r: R,
+    encoding: &'static Encoding,
+    count: &mut Counts,
+    opt: &Opt,
+) -> io::Result<()> {
+    let bytes = count.bytes;
+    let mut reader = DecodeReader::new(r, encoding);
+
+    count_window(counter, &mut reader, count, opt)?;
+    count.bytes = bytes + reader.raw_bytes;
+    Ok(())
+}
+
+// Divide the input into --record-bytes records
+fn count_records(count: &mut Counts, opt: &Opt) {
+    if let Some(size) = opt.record_bytes {
+        count.records = count.bytes / size.get();
+    }
+}
+
+// Whether any line-by-line processing of the input is needed
+fn filters_lines(opt: &Opt) -> bool {
+    opt.unique || opt.line_match.is_some() || opt.skip_lines > 0 || opt.max_lines.is_some()
+}
+
+// Count input checked to be ASCII with --ascii, taking its bytes as characters
+// where the counter doesn't count them itself
+fn count_ascii<C: Counter, R: Read>(
+    counter: &C,
+    r: R,
+    count: &mut Counts,
+    opt: &Opt,
+) -> io::Result<()> {
+    if !opt.ascii {
+        return count_window(counter, r, count, opt);
+    }
+
+    let bytes = count.bytes;
+    count_window(counter, AsciiReader::new(r), count, opt)?;
+
+    if opt.chars && !counter.capabilities().chars {
+        count.chars += count.bytes - bytes;
+    }
+
+    Ok(())
+}
+
+// Count only the lines within --skip-lines and --max-lines, if given
+fn count_window<C: Counter, R: Read>(
+    counter: &C,
+    r: R,
+    count: &mut Counts,
+    opt: &Opt,
+) -> io::Result<()> {
+    if opt.skip_lines > 0 || opt.max_lines.is_some() {
+        let reader = WindowReader::new(r, opt.skip_lines, opt.max_lines, line_delimiter(opt));
+        return count_matching(counter, reader, count, opt);
+    }
+
+    count_matching(counter, r, count, opt)
+}
+
+// Count only the lines selected by --match, if given
+fn count_matching<C: Counter, R: Read>(
+    counter: &C,
+    r: R,
+    count: &mut Counts,
+    opt: &Opt,
+) -> io::Result<()> {
+    match opt.line_match {
+        Some(ref regex) => {
+            let reader = MatchReader::new(r, regex.clone(), opt.invert_match, line_delimiter(opt));
+            count_unique(counter, reader, count, opt)
+        }
+        None => count_unique(counter, r, count, opt),
+    }
+}
+
+// Count while collecting distinct lines, if --unique asks for them
+fn count_unique<C: Counter, R: Read>(
+    counter: &C,
+    r: R,
+    count: &mut Counts,
+    opt: &Opt,
+) -> io::Result<()> {
+    if !opt.unique {
+        return counter.count(r, count, opt);
+    }
+
+    let mut lines = UniqueLines::new(line_delimiter(opt));
+    counter.count(UniqueReader::new(r, &mut lines), count, opt)?;
+
+    let hashes = lines.finish();
+    count.unique_lines = hashes.len() as u64;
+    if opt.unique_global {
+        count.line_hashes = Some(Arc::new(hashes));
+    }
+
+    Ok(())
+}
+
+// Memory-map a non-empty regular file, or None if that's not possible
+fn map_file(file: &File) -> Option<Mmap> {
+    match file.metadata() {
+        Ok(md) if md.is_file() && md.len() > 0 => unsafe { Mmap::map(file) }.ok(),
+        _ => None,
+    }
+}
+
+#[test]
+fn test_mmap() {
+    let input = "foo bar\n\tbaz \u{f3}ne\r\n\nhmm";
+    let path = std::env::temp_dir().join(format!("cw-mmap-{}", std::process::id()));
+    let empty = std::env::temp_dir().join(format!("cw-mmap-empty-{}", std::process::id()));
+    std::fs::write(&path, input).unwrap();
+    std::fs::write(&empty, "").unwrap();
+
+    for opt in &[
+        Opt::builder().lines(true).build(),
+        Opt::builder()
+            .lines(true)
+            .words(true)
+            .longest_line(true)
+            .build(),
+        Opt::builder()
+            .chars(true)
+            .words(true)
+            .longest_line(true)
+            .build(),
+        Opt::builder().graphemes(true).blank_lines(true).build(),
+    ] {
+        let strategy = Strategy::try_from(opt).unwrap();
+        let mapped = Opt {
+            mmap: true,
+            ..opt.clone()
+        };
+
+        for file in &[&path, &empty] {
+            let a = strategy.count_file(file, opt).unwrap();
+            let b = strategy.count_file(file, &mapped).unwrap();
+            assert_eq!(
+                (a.lines, a.words, a.chars, a.bytes, a.longest_line),
+                (b.lines, b.words, b.chars, b.bytes, b.longest_line),
+                "{:?}",
+                opt
+            );
+            assert_eq!((a.graphemes, a.blank_lines), (b.graphemes, b.blank_lines));
+        }
+    }
+
+    std::fs::remove_file(&path).unwrap();
+    std::fs::remove_file(&empty).unwrap();
+}
+
+// The column after a tab at `col`, or simply the next column if `tab_width` is 0
+fn tab_stop(col: u64, tab_width: u64) -> u64 {
+    match col.checked_div(tab_width) {
+        Some(stops) => (stops + 1) * tab_width,
+        None => col + 1,
+    }
+}
+
+// The size of each read, as given to --buffer-size
+fn read_size(opt: &Opt) -> usize {
+    opt.buffer_size.map_or(READ_SIZE, NonZeroUsize::get)
+}
+
+// How many more bytes the last UTF-8 character in `buf` needs to be complete
+fn utf8_bytes_needed(buf: &[u8]) -> usize {
+    for (i, b) in buf.iter().rev().take(4).enumerate() {
+        if (b & 0xc0) != 0x80 {
+            let len: usize = match b {
+                0xc0..=0xdf => 2,
+                0xe0..=0xef => 3,
+                0xf0..=0xf7 => 4,
+                _ => 1,
+            };
+            return len.saturating_sub(i + 1);
+        }
+    }
+
+    0
+}
+
+// How many characters are in `bytes`, counting each byte that doesn't continue
+// a UTF-8 sequence.  This is the policy for invalid UTF-8 throughout: a stray
+// continuation byte isn't a character, and any other invalid byte is one, so
+// the bstr strategies agree with the bytecount fast paths.
+fn utf8_chars(bytes: &[u8]) -> u64 {
+    bytecount::num_chars(bytes) as u64
+}
+
+#[test]
+fn test_utf8_bytes_needed() {
+    assert_eq!(utf8_bytes_needed(b""), 0);
+    assert_eq!(utf8_bytes_needed(b"a"), 0);
+    assert_eq!(utf8_bytes_needed(b"a\xC3"), 1);
+    assert_eq!(utf8_bytes_needed(b"a\xC3\xB3"), 0);
+    assert_eq!(utf8_bytes_needed(b"\xF0\x9F"), 2);
+}
+
+// The byte ending each line, as given to --line-delimiter
+fn line_delimiter(opt: &Opt) -> u8 {
+    opt.line_delimiter.unwrap_or(b'\n')
+}
+
+// Whether to leave the carriage return of a CRLF pair out of line lengths,
+// which only applies when lines end in newlines
+fn crlf(opt: &Opt) -> bool {
+    opt.crlf && line_delimiter(opt) == b'\n'
+}
+
+// Whether the newline at `pos` ends a CRLF pair, where `tail_cr` tracks whether
+// the previous buffer ended with a carriage return
+fn cr_before(buf: &[u8], pos: usize, tail_cr: bool) -> bool {
+    if pos > 0 {
+        buf[pos - 1] == b'\r'
+    } else {
+        tail_cr
+    }
+}
+
+// The column after a run of bytes without newlines, expanding any tabs
+fn advance_column(mut col: u64, buf: &[u8], tab_width: u64) -> u64 {
+    let mut start = 0;
+    if tab_width > 0 {
+        for pos in memchr_iter(b'\t', buf) {
+            col = tab_stop(col + (pos - start) as u64, tab_width);
+            start = pos + 1;
+        }
+    }
+
+    col + (buf.len() - start) as u64
+}
+
+// Implement count and count_slice from a closure factory, whose closures are
+// called with each buffer of input and then an empty one at the end
+macro_rules! fn_count {
+    ($counter:expr) => {
+        fn count<R: Read>(&self, r: R, count: &mut Counts, opt: &Opt) -> io::Result<()> {
+            let mut reader = BufReader::with_capacity(read_size(opt), InterruptReader::new(r));
             #[allow(unused_mut)]
-            let mut counter = $counter();
+            let mut counter = $counter(opt);
 
             loop {
                 let len = {
@@ -206,13 +1691,21 @@ macro_rules! fn_count {
                 count.bytes += len as u64;
                 reader.consume(len);
 
-                if siginfo::check_signal() {
-                    let err = io::stderr();
-                    let mut errl = err.lock();
-                    let _ = count.print(&opt, &mut errl);
-                }
+                check_progress(count, opt)?;
             }
 
+            counter(&[], count);
+            Ok(())
+        }
+
+        fn count_slice(&self, buf: &[u8], count: &mut Counts, opt: &Opt) -> io::Result<()> {
+            #[allow(unused_mut)]
+            let mut counter = $counter(opt);
+
+            counter(buf, count);
+            counter(&[], count);
+            count.bytes += buf.len() as u64;
+
             Ok(())
         }
     };
@@ -233,331 +1726,2021 @@ impl Counter for BytesOnly {
         let path = path.as_ref();
         let mut count = Counts::new(path);
 
-        let bytes = std::fs::metadata(&path)
-            .iter()
-            .filter(|md| md.is_file())
-            .map(std::fs::Metadata::len)
-            .next();
+        let bytes = std::fs::metadata(path)
+            .iter()
+            .filter(|md| md.is_file())
+            .map(std::fs::Metadata::len)
+            .next();
+
+        if let Some(bytes) = bytes {
+            count.bytes = bytes;
+        } else {
+            open_file(path).and_then(|fd| self.count(fd, &mut count, opt))?;
+        }
+
+        Ok(count)
+    }
+
+    // Null counting: just let the macro count read() bytes
+    fn_count!(|_: &Opt| |_buf: &[u8], _count: &mut Counts| { /* ... */ });
+}
+
+#[test]
+fn test_bytes() {
+    let mut c = Counts::default();
+    BytesOnly
+        .count(Cursor::new(b"12345678"), &mut c, &Opt::default())
+        .unwrap();
+    assert_eq!(c.bytes, 8);
+}
+
+#[test]
+fn test_print_json() {
+    let opt = Opt {
+        lines: true,
+        bytes: true,
+        ..Opt::default()
+    };
+    let mut c = Counts::new("a \"b\".txt");
+    c.lines = 2;
+    c.words = 3;
+    c.bytes = 6;
+
+    let mut out = Vec::new();
+    c.print_json(&opt, &mut out).unwrap();
+    assert_eq!(out, br#"{"path":"a \"b\".txt","lines":2,"bytes":6}"#);
+}
+
+#[test]
+fn test_print_csv() {
+    let opt = Opt {
+        lines: true,
+        bytes: true,
+        ..Opt::default()
+    };
+    let mut c = Counts::new("a,\"b\".txt");
+    c.lines = 2;
+    c.bytes = 6;
+
+    let mut out = Vec::new();
+    c.print_csv(&opt, &mut out).unwrap();
+    assert_eq!(out, b"2,,,6,,\"a,\"\"b\"\".txt\"\n");
+}
+
+#[test]
+fn test_print_csv_extra_columns() {
+    let opt = Opt {
+        lines: true,
+        blank_lines: true,
+        count_byte: vec![b'x', b'y'],
+        ..Opt::default()
+    };
+    let mut c = Counts::new("a");
+    c.lines = 3;
+    c.blank_lines = 1;
+    c.byte_matches = vec![4, 5];
+
+    let columns: Vec<_> = c
+        .csv_columns(&opt)
+        .iter()
+        .map(|(name, _, _)| *name)
+        .collect();
+    assert_eq!(
+        columns,
+        [
+            "lines",
+            "words",
+            "chars",
+            "bytes",
+            "longest_line",
+            "blank_lines",
+            "byte_matches",
+            "byte_matches"
+        ]
+    );
+
+    let mut out = Vec::new();
+    c.print_csv(&opt, &mut out).unwrap();
+    assert_eq!(out, b"3,,,,,1,4,5,a\n");
+}
+
+#[test]
+fn test_display() {
+    let mut c = Counts::new("foo");
+    c.lines = 2;
+    c.bytes = 12;
+    assert_eq!(c.to_string(), "lines=2 bytes=12 foo");
+    assert_eq!(Counts::default().to_string(), "");
+
+    // Zeros are left out, but every value says which metric it is
+    c.path = None;
+    c.lines = 3;
+    c.bytes = 5;
+    c.words = 0;
+    // avg_line_length, derived from lines and bytes, is left out too
+    assert_eq!(c.to_string(), "lines=3 bytes=5");
+}
+
+#[test]
+fn test_print_single_metric() {
+    let opt = Opt {
+        words: true,
+        ..Opt::default()
+    };
+    let mut c = Counts {
+        words: 433,
+        ..Counts::default()
+    };
+
+    let mut out = Vec::new();
+    c.print(&opt, &mut out).unwrap();
+    assert_eq!(out, b"433\n");
+
+    c.path = Some("foo".into());
+    out.clear();
+    c.print(&opt, &mut out).unwrap();
+    assert_eq!(out, b"     433 foo\n");
+}
+
+#[test]
+fn test_print_tabs() {
+    let opt = Opt {
+        lines: true,
+        words: true,
+        tabs: true,
+        ..Opt::default()
+    };
+    let mut c = Counts::new("a b");
+    c.lines = 2;
+    c.words = 10;
+
+    let mut out = Vec::new();
+    c.print(&opt, &mut out).unwrap();
+    assert_eq!(out, b"2\t10\ta b\n");
+}
+
+#[test]
+fn test_print_header() {
+    let opt = Opt {
+        lines: true,
+        bytes: true,
+        header: true,
+        ..Opt::default()
+    };
+
+    let mut out = Vec::new();
+    Counts::print_header(&opt, 6, &mut out).unwrap();
+    assert_eq!(out, b"  lines  bytes path\n");
+}
+
+#[test]
+fn test_encoding_utf16le() {
+    let opt = Opt {
+        encoding: Some(encoding_rs::UTF_16LE),
+        ..Opt::default()
+    };
+    let input = b"\xFF\xFE\xF3\x00n\x00e\x00 \x00t\x00w\x00o\x00\n\x00";
+
+    let mut c = Counts::default();
+    Strategy::CharsWordsLinesLongest
+        .count(Cursor::new(&input[..]), &mut c, &opt)
+        .unwrap();
+    assert_eq!(c.lines, 1);
+    assert_eq!(c.words, 2);
+    assert_eq!(c.chars, 8);
+    assert_eq!(c.longest_line, 7);
+    assert_eq!(c.bytes, 18);
+}
+
+#[test]
+fn test_unique() {
+    let mut opt = Opt {
+        unique: true,
+        ..Opt::default()
+    };
+
+    let mut a = Counts::default();
+    Strategy::LinesOnly
+        .count_slice(b"foo\nbar\nfoo\n", &mut a, &opt)
+        .unwrap();
+    assert_eq!((a.lines, a.unique_lines), (3, 2));
+
+    let mut total = Counts::default();
+    total.add(&a);
+    total.add(&a);
+    assert_eq!(total.unique_lines, 4);
+
+    opt.unique_global = true;
+    let mut b = Counts::default();
+    Strategy::WordsLinesLongest
+        .count(Cursor::new(b"bar\nbaz"), &mut b, &opt)
+        .unwrap();
+    let mut c = Counts::default();
+    Strategy::WordsLinesLongest
+        .count(Cursor::new(b"foo\nbar\n"), &mut c, &opt)
+        .unwrap();
+    assert_eq!((b.unique_lines, c.unique_lines), (2, 2));
+
+    let mut total = Counts::default();
+    total.add(&b);
+    total.add(&c);
+    assert_eq!(total.unique_lines, 3);
+}
+
+#[test]
+fn test_unsupported_options() {
+    let opt = Opt {
+        words: true,
+        unicode_words: true,
+        graphemes: true,
+        ..Opt::default()
+    };
+    assert_eq!(Strategy::try_from(&opt).err(), Some(UnsupportedOptions));
+    assert!(Strategy::try_from(&Opt::default()).is_ok());
+}
+
+#[test]
+fn test_ignore_missing() {
+    let path = "/nonexistent/cw-test";
+    let opt = Opt {
+        lines: true,
+        ..Opt::default()
+    };
+    assert!(Strategy::LinesOnly.count_file(path, &opt).is_err());
+
+    let opt = Opt {
+        ignore_missing: true,
+        ..opt
+    };
+    let c = Strategy::LinesOnly.count_file(path, &opt).unwrap();
+    assert_eq!((c.path, c.lines), (Some(path.into()), 0));
+}
+
+#[test]
+fn test_line_match() {
+    let opt = Opt {
+        line_match: Some(regex::bytes::Regex::new("o").unwrap()),
+        ..Opt::default()
+    };
+    let input = b"one two\nthree\nfour\n";
+
+    let mut c = Counts::default();
+    Strategy::WordsLinesLongest
+        .count(Cursor::new(input), &mut c, &opt)
+        .unwrap();
+    assert_eq!((c.lines, c.words, c.bytes), (2, 3, 13));
+
+    let opt = Opt {
+        invert_match: true,
+        ..opt
+    };
+    let mut c = Counts::default();
+    Strategy::BytesOnly
+        .count_slice(input, &mut c, &opt)
+        .unwrap();
+    assert_eq!(c.bytes, 6);
+}
+
+#[test]
+fn test_line_window() {
+    let opt = Opt {
+        skip_lines: 1,
+        max_lines: Some(2),
+        ..Opt::default()
+    };
+    let input = b"one\ntwo three\nfour\nfive";
+
+    let mut c = Counts::default();
+    Strategy::WordsLinesLongest
+        .count(Cursor::new(input), &mut c, &opt)
+        .unwrap();
+    assert_eq!((c.lines, c.words, c.bytes), (2, 3, 15));
+
+    let opt = Opt {
+        skip_lines: 3,
+        max_lines: None,
+        ..opt
+    };
+    let mut c = Counts::default();
+    Strategy::LinesOnly
+        .count_slice(input, &mut c, &opt)
+        .unwrap();
+    assert_eq!((c.lines, c.bytes), (0, 4));
+}
+
+#[test]
+fn test_records() {
+    let opt = Opt {
+        record_bytes: std::num::NonZeroU64::new(4),
+        ..Opt::default()
+    };
+
+    let mut c = Counts::default();
+    Strategy::BytesOnly
+        .count_slice(b"aaaabbbbcc", &mut c, &opt)
+        .unwrap();
+    assert_eq!((c.records, c.bytes), (2, 10));
+
+    let mut total = Counts::default();
+    total.add(&c);
+    total.add(&c);
+    assert_eq!(total.records, 4);
+}
+
+#[test]
+fn test_count_byte() {
+    let opt = Opt {
+        count_byte: vec![b',', b'\n'],
+        ..Opt::default()
+    };
+    let csv = b"name,size,path\nfoo,1,/tmp/foo\nbar,22,/tmp/bar\n";
+
+    let mut c = Counts::default();
+    Strategy::BytesOnly
+        .count(Cursor::new(&csv[..]), &mut c, &opt)
+        .unwrap();
+    assert_eq!(c.byte_matches, [6, 3]);
+
+    let mut d = Counts::default();
+    Strategy::BytesOnly.count_slice(csv, &mut d, &opt).unwrap();
+    assert_eq!(d.byte_matches, [6, 3]);
+
+    let mut total = Counts::default();
+    total.add(&c);
+    total.add(&d);
+    assert_eq!(total.byte_matches, [12, 6]);
+
+    let mut out = vec![];
+    total.print_json(&opt, &mut out).unwrap();
+    assert_eq!(out, b"{\"byte_matches\":[12,6]}");
+}
+
+#[test]
+fn test_bytes_range() {
+    let opt = Opt {
+        bytes_range: Some("4..12".parse().unwrap()),
+        lines: true,
+        ..Opt::default()
+    };
+    let input = b"one\ntwo\nthree\nfour\n";
+
+    let mut c = Counts::default();
+    Strategy::LinesOnly
+        .count(Cursor::new(&input[..]), &mut c, &opt)
+        .unwrap();
+    assert_eq!((c.lines, c.bytes), (1, 8));
+
+    let mut d = Counts::default();
+    Strategy::LinesOnly
+        .count_slice(input, &mut d, &opt)
+        .unwrap();
+    assert_eq!((d.lines, d.bytes), (1, 8));
+
+    let path = std::env::temp_dir().join(format!("cw-bytes-range-{}", std::process::id()));
+    std::fs::write(&path, input).unwrap();
+    let e = Strategy::BytesOnly.count_file(&path, &opt);
+    let f = Strategy::LinesOnly.count_file(
+        &path,
+        &Opt {
+            bytes_range: Some("18..".parse().unwrap()),
+            ..opt
+        },
+    );
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(e.unwrap().bytes, 8);
+    assert_eq!(f.map(|f| (f.lines, f.bytes)).unwrap(), (1, 1));
+}
+
+#[test]
+fn test_skip_binary() {
+    let opt = Opt {
+        skip_binary: true,
+        ..Opt::default()
+    };
+    let dir = std::env::temp_dir();
+    let binary = dir.join(format!("cw-binary-{}", std::process::id()));
+    let text = dir.join(format!("cw-text-{}", std::process::id()));
+    std::fs::write(&binary, b"ELF\0\x01\x02 words\n").unwrap();
+    std::fs::write(&text, b"just words\n").unwrap();
+
+    let b = Strategy::WordsLinesLongest.count_file(&binary, &opt);
+    let t = Strategy::WordsLinesLongest.count_file(&text, &opt);
+    let forced = Strategy::WordsLinesLongest.count_file(&binary, &Opt::default());
+    std::fs::remove_file(&binary).unwrap();
+    std::fs::remove_file(&text).unwrap();
+
+    assert!(BinaryFile::is(&b.unwrap_err()));
+    assert_eq!(t.unwrap().words, 2);
+    assert_eq!(forced.unwrap().lines, 1);
+}
+
+#[test]
+fn test_skip_bom() {
+    let opt = Opt {
+        skip_bom: true,
+        ..Opt::default()
+    };
+
+    for strategy in &[
+        Strategy::CharsOnly,
+        Strategy::CharsLinesLongest,
+        Strategy::CharsWordsLinesLongest,
+    ] {
+        let mut c = Counts::default();
+        strategy
+            .count(Cursor::new(b"\xEF\xBB\xBFab"), &mut c, &opt)
+            .unwrap();
+        assert_eq!((c.chars, c.bytes), (2, 5));
+
+        let mut c = Counts::default();
+        strategy
+            .count_slice(b"\xEF\xBB\xBFab", &mut c, &opt)
+            .unwrap();
+        assert_eq!((c.chars, c.bytes), (2, 5));
+    }
+}
+
+struct LinesOnly;
+impl Counter for LinesOnly {
+    fn capabilities(&self) -> Capability {
+        Capability {
+            rank: 1,
+            bytes: true,
+            lines: true,
+            ..Capability::default()
+        }
+    }
+
+    // Fast path for -l
+    fn_count!(|opt: &Opt| {
+        let delim = line_delimiter(opt);
+        let count_partial = opt.count_partial_line;
+        let mut partial = false;
+
+        move |buf: &[u8], count: &mut Counts| {
+            if buf.is_empty() {
+                if count_partial && partial {
+                    count.lines += 1;
+                }
+                return;
+            }
+
+            count.lines += bytecount::count(buf, delim) as u64;
+            partial = buf.last() != Some(&delim);
+        }
+    });
+}
+
+#[test]
+fn test_lines() {
+    let mut c = Counts::default();
+    LinesOnly
+        .count(Cursor::new(b"\n\n\n\n\n\n\n\n"), &mut c, &Opt::default())
+        .unwrap();
+    assert_eq!(c.lines, 8);
+}
+
+struct CharsOnly;
+impl Counter for CharsOnly {
+    fn capabilities(&self) -> Capability {
+        Capability {
+            rank: 1,
+            bytes: true,
+            chars: true,
+            ..Capability::default()
+        }
+    }
+
+    // Fast path for -m
+    fn_count!(|_: &Opt| |buf: &[u8], count: &mut Counts| {
+        count.chars += bytecount::num_chars(buf) as u64;
+    });
+}
+
+#[test]
+fn test_chars() {
+    let mut c = Counts::default();
+    CharsOnly
+        .count(Cursor::new(b"fo\xC3\xB3"), &mut c, &Opt::default())
+        .unwrap();
+    assert_eq!(c.chars, 3);
+    assert_eq!(c.bytes, 4);
+}
+
+#[test]
+fn test_invalid_utf8_chars() {
+    // A lone invalid byte is one character, a stray continuation byte none,
+    // whichever strategy counts them
+    let input = b"a\xFF b\x80\n\xFF\n";
+    let opt = Opt {
+        chars: true,
+        ..Opt::default()
+    };
+
+    for strategy in &[
+        Strategy::CharsOnly,
+        Strategy::CharsLinesLongest,
+        Strategy::CharsWordsLinesLongest,
+        Strategy::UnicodeWordsCharsLinesLongest,
+        Strategy::GraphemesCharsWordsLinesLongest,
+    ] {
+        let mut c = Counts::default();
+        strategy
+            .count(Cursor::new(&input[..]), &mut c, &opt)
+            .unwrap();
+        assert_eq!(c.chars, 7, "{:?}", strategy);
+        if !matches!(strategy, Strategy::CharsOnly) {
+            assert_eq!(c.longest_line, 4, "{:?}", strategy);
+        }
+    }
+}
+
+/// Check the `bytecount` fast paths for -l and -m agree with naive counts
+/// over a generated corpus, printing each mismatch or OK to `out`.
+///
+/// Returns whether every count matched.
+pub fn self_test<W: Write>(mut out: W) -> io::Result<bool> {
+    let opt = Opt::default();
+    let mut ok = true;
+
+    // Pseudo-random text of every length around the SIMD block sizes, plus
+    // some spanning read buffers, with newlines and multibyte characters
+    let mut seed = 0x2545_f491_u32;
+    let lengths = (0..1024).chain([READ_SIZE - 1, READ_SIZE, READ_SIZE * 3 + 7]);
+    for (i, len) in lengths.enumerate() {
+        let sample: Vec<u8> = (0..len)
+            .map(|_| {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                match (seed >> 16) % 16 {
+                    0 => b'\n',
+                    1 => 0xC3,
+                    2 => 0xB3,
+                    n => b'a' + n as u8,
+                }
+            })
+            .collect();
+
+        let lines = sample.iter().filter(|b| **b == b'\n').count() as u64;
+        let chars = sample.iter().filter(|b| (**b & 0xc0) != 0x80).count() as u64;
+
+        for (strategy, expected) in [(Strategy::LinesOnly, lines), (Strategy::CharsOnly, chars)] {
+            let mut streamed = Counts::default();
+            let mut sliced = Counts::default();
+            strategy.count(Cursor::new(&sample), &mut streamed, &opt)?;
+            strategy.count_slice(&sample, &mut sliced, &opt)?;
+
+            for got in [&streamed, &sliced] {
+                let got = got.lines + got.chars;
+                if got != expected {
+                    ok = false;
+                    writeln!(
+                        out,
+                        "{:?} mismatch on sample {} ({} bytes): expected {}, got {}",
+                        strategy, i, len, expected, got
+                    )?;
+                }
+            }
+        }
+    }
+
+    if ok {
+        writeln!(out, "OK")?;
+    }
+
+    Ok(ok)
+}
+
+#[test]
+fn test_self_test() {
+    assert!(self_test(io::sink()).unwrap());
+}
+
+struct LinesLongest;
+impl Counter for LinesLongest {
+    fn capabilities(&self) -> Capability {
+        Capability {
+            rank: 30,
+            bytes: true,
+            lines: true,
+            longest_line: true,
+            ..Capability::default()
+        }
+    }
+
+    // Fast path for -lL
+    fn_count!(|opt: &Opt| {
+        let delim = line_delimiter(opt);
+        let tab_width = opt.tab_width;
+        let crlf = crlf(opt);
+        let count_partial = opt.count_partial_line;
+        let mut line_len = 0_u64;
+        let mut tail_cr = false;
+
+        move |buf: &[u8], count: &mut Counts| {
+            // An empty buffer marks the end of the input
+            if buf.is_empty() {
+                count.end_partial_line(line_len, count_partial);
+                return;
+            }
+            let mut start = 0;
+            for pos in memchr_iter(delim, buf) {
+                line_len = advance_column(line_len, &buf[start..pos], tab_width);
+
+                if crlf && cr_before(buf, pos, tail_cr) {
+                    line_len -= 1;
+                }
+
+                count.end_line(line_len);
+
+                line_len = 0;
+
+                count.lines += 1;
+                start = pos + 1;
+            }
+
+            line_len = advance_column(line_len, &buf[start..], tab_width);
+            tail_cr = buf.last() == Some(&b'\r');
+        }
+    });
+}
+
+#[test]
+fn test_lines_longest() {
+    let mut c = Counts::default();
+    LinesLongest
+        .count(
+            Cursor::new(b"foo\nbar\nmoooo\nhmm\n"),
+            &mut c,
+            &Opt::default(),
+        )
+        .unwrap();
+    assert_eq!(c.lines, 4);
+    assert_eq!(c.longest_line, 5);
+}
+
+#[test]
+fn test_lines_longest_buffer_boundary() {
+    // A long line straddling the first read, then a newline ending one read
+    // followed by another starting the next, with a CRLF split across reads
+    let mut input = b"ab\n".to_vec();
+    input.resize(READ_SIZE + 10, b'a');
+    input.push(b'\n');
+    input.resize(READ_SIZE * 2 - 1, b'b');
+    input.extend_from_slice(b"\n\nc\r");
+    input.resize(READ_SIZE * 3 - 1, b'c');
+    input.extend_from_slice(b"\r\n");
+    assert_eq!(input[READ_SIZE * 2 - 1], b'\n');
+    assert_eq!(input[READ_SIZE * 3 - 1], b'\r');
+
+    let opt = Opt {
+        crlf: true,
+        ..Opt::default()
+    };
+
+    for strategy in &[
+        Strategy::LinesLongest,
+        Strategy::WordsLinesLongest,
+        Strategy::CharsLinesLongest,
+    ] {
+        let mut c = Counts::default();
+        strategy.count(Cursor::new(&input), &mut c, &opt).unwrap();
+        assert_eq!(c.lines, 5);
+        assert_eq!(c.longest_line, READ_SIZE as u64 + 7);
+        assert_eq!(c.longest_line_at, 2);
+        assert_eq!(c.shortest_line, Some(0));
+
+        let mut d = Counts::default();
+        strategy.count_slice(&input, &mut d, &opt).unwrap();
+        assert_eq!(
+            (c.lines, c.longest_line, c.shortest_line),
+            (d.lines, d.longest_line, d.shortest_line)
+        );
+    }
+}
+
+#[test]
+fn test_longest_unterminated_line() {
+    let input = b"short\nunterminatedverylongline";
+
+    for strategy in &[
+        Strategy::LinesLongest,
+        Strategy::WordsLinesLongest,
+        Strategy::CharsLinesLongest,
+        Strategy::CharsWordsLinesLongest,
+        Strategy::UnicodeWordsCharsLinesLongest,
+        Strategy::GraphemesCharsWordsLinesLongest,
+    ] {
+        let mut c = Counts::default();
+        strategy
+            .count(Cursor::new(&input[..]), &mut c, &Opt::default())
+            .unwrap();
+        assert_eq!((c.lines, c.longest_line, c.longest_line_at), (1, 24, 2));
+
+        let mut d = Counts::default();
+        strategy
+            .count_slice(input, &mut d, &Opt::default())
+            .unwrap();
+        assert_eq!(d.longest_line, 24);
+    }
+}
+
+#[test]
+fn test_count_partial_line() {
+    let opt = Opt {
+        count_partial_line: true,
+        ..Opt::default()
+    };
+
+    for strategy in &[
+        Strategy::LinesOnly,
+        Strategy::LinesLongest,
+        Strategy::WordsLinesLongest,
+        Strategy::CharsLinesLongest,
+        Strategy::CharsWordsLinesLongest,
+        Strategy::UnicodeWordsCharsLinesLongest,
+        Strategy::GraphemesCharsWordsLinesLongest,
+    ] {
+        // Newlines counted, and lines including an unterminated last one
+        for (input, lines, partial_lines) in &[
+            (&b""[..], 0, 0),
+            (&b"\n"[..], 1, 1),
+            (&b"\n\n"[..], 2, 2),
+            (&b"foo"[..], 0, 1),
+            (&b"foo\n"[..], 1, 1),
+            (&b"foo\n\n"[..], 2, 2),
+            (&b"foo\nbar"[..], 1, 2),
+            (&b"foo\nbar\n"[..], 2, 2),
+            (&b"foo\r\nbar\r"[..], 1, 2),
+            (&b"\nfoo"[..], 1, 2),
+        ] {
+            let mut c = Counts::default();
+            strategy
+                .count(Cursor::new(input), &mut c, &Opt::default())
+                .unwrap();
+            assert_eq!(c.lines, *lines);
+
+            let mut c = Counts::default();
+            strategy.count(Cursor::new(input), &mut c, &opt).unwrap();
+            assert_eq!(c.lines, *partial_lines);
+
+            let mut c = Counts::default();
+            strategy.count_slice(input, &mut c, &opt).unwrap();
+            assert_eq!(c.lines, *partial_lines);
+        }
+    }
+}
+
+#[test]
+fn test_buffer_size() {
+    let input = "one two\tthree\n\n\u{f3}ne  four\r\nfive\n six".repeat(50);
+
+    for strategy in &[
+        Strategy::LinesOnly,
+        Strategy::CharsOnly,
+        Strategy::LinesLongest,
+        Strategy::WordsLinesLongest,
+        Strategy::CharsLinesLongest,
+        Strategy::CharsWordsLinesLongest,
+        Strategy::UnicodeWordsCharsLinesLongest,
+        Strategy::GraphemesCharsWordsLinesLongest,
+    ] {
+        let mut expected = Counts::default();
+        strategy
+            .count(Cursor::new(&input), &mut expected, &Opt::default())
+            .unwrap();
+
+        for size in &[1, 3, 64, 1024 * 1024] {
+            // These split words and graphemes at lines longer than a read
+            let segments = matches!(
+                strategy,
+                Strategy::UnicodeWordsCharsLinesLongest | Strategy::GraphemesCharsWordsLinesLongest
+            );
+            if segments && *size < 32 {
+                continue;
+            }
+
+            let opt = Opt {
+                buffer_size: NonZeroUsize::new(*size),
+                ..Opt::default()
+            };
+
+            let mut c = Counts::default();
+            strategy.count(Cursor::new(&input), &mut c, &opt).unwrap();
+            assert_eq!(
+                (
+                    c.lines,
+                    c.words,
+                    c.chars,
+                    c.bytes,
+                    c.longest_line,
+                    c.graphemes
+                ),
+                (
+                    expected.lines,
+                    expected.words,
+                    expected.chars,
+                    expected.bytes,
+                    expected.longest_line,
+                    expected.graphemes
+                ),
+                "{:?} with {} byte buffers",
+                strategy,
+                size
+            );
+        }
+    }
+}
+
+#[test]
+fn test_shortest_line() {
+    let input = b"foo
+bar
+moooo
+hm
+unterminated";
+
+    let mut c = Counts::default();
+    LinesLongest
+        .count(Cursor::new(input), &mut c, &Opt::default())
+        .unwrap();
+    assert_eq!(c.shortest_line, Some(2));
+
+    let mut d = Counts::default();
+    CharsWordsLinesLongest
+        .count(Cursor::new(b"\xC3\xB3ne\n"), &mut d, &Opt::default())
+        .unwrap();
+    assert_eq!(d.shortest_line, Some(3));
+
+    let mut e = Counts::default();
+    WordsLinesLongest
+        .count(Cursor::new(b""), &mut e, &Opt::default())
+        .unwrap();
+    assert_eq!(e.shortest_line, None);
+
+    let mut total = Counts::default();
+    total.add(&c);
+    total.add(&d);
+    total.add(&e);
+    assert_eq!(total.shortest_line, Some(2));
+}
+
+#[test]
+fn test_lines_longest_tabs() {
+    let opt = Opt {
+        tab_width: 8,
+        ..Opt::default()
+    };
+
+    let mut c = Counts::default();
+    LinesLongest
+        .count(Cursor::new(b"a\tb\n"), &mut c, &opt)
+        .unwrap();
+    assert_eq!(c.longest_line, 9);
+    assert_eq!(c.bytes, 4);
+
+    let mut c = Counts::default();
+    WordsLinesLongest
+        .count(Cursor::new(b"a\tb\n"), &mut c, &opt)
+        .unwrap();
+    assert_eq!(c.longest_line, 9);
+
+    let mut c = Counts::default();
+    CharsLinesLongest
+        .count(Cursor::new(b"a\tb\n"), &mut c, &Opt::default())
+        .unwrap();
+    assert_eq!(c.longest_line, 3);
+}
+
+#[test]
+fn test_longest_crlf() {
+    let opt = Opt {
+        crlf: true,
+        ..Opt::default()
+    };
+    let input = b"foo\r\nbarbar\r\n";
+
+    let mut c = Counts::default();
+    LinesLongest
+        .count(Cursor::new(input), &mut c, &opt)
+        .unwrap();
+    assert_eq!((c.lines, c.longest_line), (2, 6));
+
+    let mut c = Counts::default();
+    WordsLinesLongest
+        .count(Cursor::new(input), &mut c, &opt)
+        .unwrap();
+    assert_eq!((c.lines, c.words, c.longest_line), (2, 2, 6));
+
+    let mut c = Counts::default();
+    CharsWordsLinesLongest
+        .count(Cursor::new(input), &mut c, &opt)
+        .unwrap();
+    assert_eq!((c.lines, c.words, c.longest_line), (2, 2, 6));
+
+    let mut c = Counts::default();
+    LinesLongest
+        .count(Cursor::new(input), &mut c, &Opt::default())
+        .unwrap();
+    assert_eq!(c.longest_line, 7);
+}
+
+#[test]
+fn test_merged() {
+    let mut a = Counts::new("a");
+    a.lines = 2;
+    a.longest_line = 5;
+    let mut b = Counts::new("b");
+    b.lines = 3;
+    b.longest_line = 4;
+
+    let merged = Counts::new("total").merged(&a).merged(&b);
+    assert_eq!((merged.lines, merged.longest_line), (5, 5));
+    assert_eq!(merged.path, Some("total".into()));
+
+    let mut total = Counts::default() + &a;
+    total += &b;
+    assert_eq!((total.lines, total.longest_line), (5, 5));
+    assert_eq!(total.longest_line_path, Some("a".into()));
+}
+
+#[test]
+fn test_longest_line_at() {
+    let mut c = Counts::new("a");
+    LinesLongest
+        .count(
+            Cursor::new(b"foo\nmoooo\nbar\nhmmmm\n"),
+            &mut c,
+            &Opt::default(),
+        )
+        .unwrap();
+    assert_eq!(c.longest_line_at, 2);
+
+    let mut d = Counts::new("b");
+    CharsWordsLinesLongest
+        .count(Cursor::new(b"foo\nbar\nmoooooo\n"), &mut d, &Opt::default())
+        .unwrap();
+    assert_eq!(d.longest_line_at, 3);
+
+    let mut total = Counts::new("total");
+    total.add(&c);
+    total.add(&d);
+    assert_eq!(total.longest_line_at, 3);
+    assert_eq!(total.longest_line_path, Some("b".into()));
+}
+
+#[test]
+fn test_is_empty_and_reset() {
+    assert!(Counts::default().is_empty());
+    assert!(Counts::new("a").is_empty());
+
+    let mut c = Counts::new("a");
+    WordsLinesLongest
+        .count(Cursor::new(b""), &mut c, &Opt::default())
+        .unwrap();
+    assert!(c.is_empty());
+
+    WordsLinesLongest
+        .count(Cursor::new(b"one two\n"), &mut c, &Opt::default())
+        .unwrap();
+    assert!(!c.is_empty());
+
+    c.reset();
+    assert!(c.is_empty());
+    assert_eq!(c.path, Some("a".into()));
+    assert_eq!(c.shortest_line, None);
+
+    c.byte_matches = vec![0, 1];
+    assert!(!c.is_empty());
+    c.reset();
+    assert!(c.byte_matches.is_empty());
+}
+
+#[test]
+fn test_count_slice() {
+    let input = b"foo\nbar\tbaz\nmoooo\nhmm";
+    let opt = Opt {
+        tab_width: 8,
+        ..Opt::default()
+    };
+
+    let mut a = Counts::default();
+    let mut b = Counts::default();
+    LinesLongest
+        .count(Cursor::new(input), &mut a, &opt)
+        .unwrap();
+    LinesLongest.count_slice(input, &mut b, &opt).unwrap();
+    assert_eq!(
+        (a.lines, a.bytes, a.longest_line),
+        (b.lines, b.bytes, b.longest_line)
+    );
+}
+
+#[test]
+fn test_line_delimiter() {
+    let opt = Opt {
+        line_delimiter: Some(b'\0'),
+        ..Opt::default()
+    };
+    let input = b"foo\nbar\0ab\0abcdef";
+
+    for strategy in &[
+        Strategy::LinesOnly,
+        Strategy::LinesLongest,
+        Strategy::WordsLinesLongest,
+        Strategy::CharsLinesLongest,
+        Strategy::CharsWordsLinesLongest,
+        Strategy::UnicodeWordsCharsLinesLongest,
+        Strategy::GraphemesCharsWordsLinesLongest,
+    ] {
+        let mut c = Counts::default();
+        strategy
+            .count(Cursor::new(&input[..]), &mut c, &opt)
+            .unwrap();
+        assert_eq!(c.lines, 2);
+        assert_eq!(c.bytes, input.len() as u64);
+
+        if strategy.capabilities().longest_line {
+            assert_eq!(c.longest_line, 7);
+        }
+    }
+
+    let mut c = Counts::default();
+    WordsLinesLongest
+        .count(Cursor::new(&input[..]), &mut c, &opt)
+        .unwrap();
+    assert_eq!(c.words, 4);
+}
+
+#[test]
+fn test_cr_lines() {
+    // Classic Mac OS text, where --crlf has no CRLF pairs to look for
+    let opt = Opt {
+        line_delimiter: Some(b'\r'),
+        crlf: true,
+        ..Opt::default()
+    };
+
+    for strategy in &[
+        Strategy::LinesOnly,
+        Strategy::LinesLongest,
+        Strategy::WordsLinesLongest,
+        Strategy::CharsLinesLongest,
+        Strategy::CharsWordsLinesLongest,
+        Strategy::UnicodeWordsCharsLinesLongest,
+        Strategy::GraphemesCharsWordsLinesLongest,
+    ] {
+        let mut c = Counts::default();
+        strategy
+            .count(Cursor::new(&b"a\rb\rc\r"[..]), &mut c, &opt)
+            .unwrap();
+        assert_eq!(c.lines, 3, "{:?}", strategy);
+
+        // A newline is then part of the line after it
+        let mut c = Counts::default();
+        strategy
+            .count(Cursor::new(&b"a\r\r\nbc\r"[..]), &mut c, &opt)
+            .unwrap();
+        assert_eq!(c.lines, 3, "{:?}", strategy);
+
+        if strategy.capabilities().longest_line {
+            assert_eq!(c.longest_line, 3, "{:?}", strategy);
+        }
+    }
+}
+
+// Whether a sequence of bytes is a whole UTF-8 encoded whitespace character
+// outside ASCII, or None if it isn't the start of one
+fn unicode_space(seq: &[u8]) -> Option<bool> {
+    match seq {
+        [0xc2, 0x85]
+        | [0xc2, 0xa0]
+        | [0xe1, 0x9a, 0x80]
+        | [0xe2, 0x80, 0x80..=0x8a]
+        | [0xe2, 0x80, 0xa8]
+        | [0xe2, 0x80, 0xa9]
+        | [0xe2, 0x80, 0xaf]
+        | [0xe2, 0x81, 0x9f]
+        | [0xe3, 0x80, 0x80] => Some(true),
+        [0xc2]
+        | [0xe1]
+        | [0xe1, 0x9a]
+        | [0xe2]
+        | [0xe2, 0x80]
+        | [0xe2, 0x81]
+        | [0xe3]
+        | [0xe3, 0x80] => Some(false),
+        _ => None,
+    }
+}
+
+#[test]
+fn test_unicode_space() {
+    for c in (0x80..=0x10ffff).filter_map(std::char::from_u32) {
+        let mut buf = [0; 4];
+        let bytes = c.encode_utf8(&mut buf).as_bytes();
+        assert_eq!(
+            unicode_space(bytes) == Some(true),
+            c.is_whitespace(),
+            "{:?}",
+            c
+        );
+    }
+}
+
+struct WordsLinesLongest;
+impl Counter for WordsLinesLongest {
+    fn capabilities(&self) -> Capability {
+        Capability {
+            rank: 150,
+            words: true,
+            longest_word: true,
+            max_words_per_line: true,
+            word_separators: true,
+            bytes: true,
+            lines: true,
+            longest_line: true,
+            blank_lines: true,
+            paragraphs: true,
+            ..Capability::default()
+        }
+    }
+
+    // Word count split on whitespace, like char::is_whitespace, or on
+    // --word-separators
+    fn_count!(|opt: &Opt| {
+        let delim = line_delimiter(opt);
+        let separators = opt
+            .word_separators
+            .clone()
+            .unwrap_or_else(ByteSet::ascii_whitespace);
+        let unicode_spaces = opt.word_separators.is_none();
+        let tab_width = opt.tab_width;
+        let crlf = crlf(opt);
+        let count_partial = opt.count_partial_line;
+        let mut line_len = 0_u64;
+        let mut in_word = false;
+        let mut word_len = 0_u64;
+        let mut line_words = 0_u64;
+        let mut blank = true;
+        let mut prev_blank = true;
+        let mut tail_cr = false;
+
+        // Bytes of what may be a multibyte space, which are counted as part of
+        // a word until it's complete, when the state before it is restored
+        let mut space = [0_u8; 3];
+        let mut space_len = 0;
+        let mut before_space = (0, 0, 0, 0, 0, true);
+
+        move |buf: &[u8], count: &mut Counts| {
+            // An empty buffer marks the end of the input
+            if buf.is_empty() {
+                count.end_partial_line(line_len, count_partial);
+                count.max_words_per_line = count.max_words_per_line.max(line_words);
+                return;
+            }
+            for (i, b) in buf.iter().enumerate() {
+                if *b == delim || separators.contains(*b) {
+                    in_word = false;
+                    space_len = 0;
+
+                    if *b == delim {
+                        if crlf && cr_before(buf, i, tail_cr) {
+                            line_len -= 1;
+                        }
+
+                        count.end_line(line_len);
+                        count.max_words_per_line = count.max_words_per_line.max(line_words);
+                        line_words = 0;
+
+                        if blank {
+                            count.blank_lines += 1;
+                        }
+
+                        prev_blank = blank;
+
+                        blank = true;
+                        line_len = 0;
+                        count.lines += 1;
+                    } else if *b == b'\t' {
+                        line_len = tab_stop(line_len, tab_width);
+                    } else {
+                        line_len += 1;
+                    }
+                } else {
+                    if *b >= 0x80 && unicode_spaces {
+                        if space_len > 0 {
+                            space[space_len] = *b;
+                            space_len += 1;
+
+                            match unicode_space(&space[..space_len]) {
+                                Some(true) => {
+                                    let (len, words, on_line, longest, paragraphs, was_blank) =
+                                        before_space;
+                                    word_len = len;
+                                    count.words = words;
+                                    line_words = on_line;
+                                    count.longest_word = longest;
+                                    count.paragraphs = paragraphs;
+                                    blank = was_blank;
+                                    in_word = false;
+                                    space_len = 0;
+                                    line_len += 1;
+                                    continue;
+                                }
+                                Some(false) => (),
+                                None => space_len = 0,
+                            }
+                        }
+
+                        if space_len == 0 && unicode_space(&[*b]).is_some() {
+                            before_space = (
+                                word_len,
+                                count.words,
+                                line_words,
+                                count.longest_word,
+                                count.paragraphs,
+                                blank,
+                            );
+                            space[0] = *b;
+                            space_len = 1;
+                        }
+                    }
+
+                    if !in_word {
+                        count.words += 1;
+                        line_words += 1;
+                        word_len = 0;
+                    }
+                    // Count UTF-8 characters by skipping continuation bytes
+                    if (b & 0xc0) != 0x80 {
+                        word_len += 1;
+                        count.longest_word = count.longest_word.max(word_len);
+                    }
+                    in_word = true;
+                    if blank && prev_blank {
+                        count.paragraphs += 1;
+                    }
+                    blank = false;
+                    line_len += 1;
+                }
+            }
+
+            tail_cr = buf.last() == Some(&b'\r');
+        }
+    });
+}
+
+#[test]
+fn test_words_lines_longest() {
+    let mut c = Counts::default();
+    WordsLinesLongest
+        .count(
+            Cursor::new(b"one two\nthree\nfour five six\n"),
+            &mut c,
+            &Opt::default(),
+        )
+        .unwrap();
+    assert_eq!(c.lines, 3);
+    assert_eq!(c.words, 6);
+    assert_eq!(c.longest_line, 13);
+}
+
+#[test]
+fn test_word_separators() {
+    let opt = Opt {
+        word_separators: Some(",;".parse().unwrap()),
+        words: true,
+        ..Opt::default()
+    };
+    let strategy = Strategy::try_from(&opt).unwrap();
+
+    let mut c = Counts::default();
+    strategy
+        .count(Cursor::new(b"a b,c;;d\ne,f\n"), &mut c, &opt)
+        .unwrap();
+    assert_eq!((c.lines, c.words, c.longest_word), (2, 5, 3));
+    assert_eq!(c.longest_line, 8);
 
-        if let Some(bytes) = bytes {
-            count.bytes = bytes;
-        } else {
-            open_file(&path).and_then(|fd| self.count(fd, &mut count, &opt))?;
-        }
+    let opt = Opt { chars: true, ..opt };
+    assert!(Strategy::try_from(&opt).is_err());
+}
 
-        Ok(count)
+#[test]
+fn test_ascii() {
+    let opt = Opt {
+        ascii: true,
+        chars: true,
+        words: true,
+        longest_line: true,
+        ..Opt::default()
+    };
+    let strategy = Strategy::try_from(&opt).unwrap();
+    assert!(matches!(strategy, Strategy::WordsLinesLongest));
+
+    let input = b"one two\nthree\n";
+    let mut streamed = Counts::default();
+    let mut sliced = Counts::default();
+    strategy
+        .count(Cursor::new(&input[..]), &mut streamed, &opt)
+        .unwrap();
+    strategy.count_slice(input, &mut sliced, &opt).unwrap();
+    for c in &[streamed, sliced] {
+        assert_eq!(c.chars, 14);
+        assert_eq!(c.words, 3);
+        assert_eq!(c.longest_line, 7);
     }
 
-    // Null counting: just let the macro count read() bytes
-    fn_count!(|| |_buf: &[u8], _count: &mut Counts| { /* ... */ });
+    let input = b"one\ntw\xC3\xB3\n";
+    let mut c = Counts::default();
+    let err = strategy
+        .count(Cursor::new(&input[..]), &mut c, &opt)
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert!(strategy.count_slice(input, &mut c, &opt).is_err());
 }
 
 #[test]
-fn test_bytes() {
+fn test_unicode_space_words() {
+    // Non-breaking and em spaces separate words whether or not counting chars,
+    // while other multibyte characters don't
+    let input = "one\u{a0}two th\u{2003}ree\n\u{a0}\n\u{a3}4\u{e9}\n".as_bytes();
+
+    for buffer_size in &[None, NonZeroUsize::new(1), NonZeroUsize::new(2)] {
+        let opt = Opt {
+            buffer_size: *buffer_size,
+            ..Opt::default()
+        };
+
+        for strategy in &[
+            Strategy::WordsLinesLongest,
+            Strategy::CharsWordsLinesLongest,
+            Strategy::GraphemesCharsWordsLinesLongest,
+        ] {
+            let mut c = Counts::default();
+            strategy.count(Cursor::new(input), &mut c, &opt).unwrap();
+            let context = format!("{:?} with {:?}", strategy, buffer_size);
+            assert_eq!(c.words, 5, "{}", context);
+            assert_eq!(c.longest_word, 3, "{}", context);
+            assert_eq!(c.blank_lines, 1, "{}", context);
+            assert_eq!(c.paragraphs, 2, "{}", context);
+        }
+    }
+
+    // Explicit separators are taken as they are, so a lone space doesn't split
+    // the first two words, but a non-breaking space alone is a word
+    let opt = Opt {
+        word_separators: Some(" ".parse().unwrap()),
+        ..Opt::default()
+    };
     let mut c = Counts::default();
-    BytesOnly
-        .count(Cursor::new(b"12345678"), &mut c, &Opt::default())
+    WordsLinesLongest
+        .count(Cursor::new(input), &mut c, &opt)
         .unwrap();
-    assert_eq!(c.bytes, 8);
+    assert_eq!(c.words, 4);
 }
 
-struct LinesOnly;
-impl Counter for LinesOnly {
-    fn capabilities(&self) -> Capability {
-        Capability {
-            rank: 1,
-            bytes: true,
-            lines: true,
-            ..Capability::default()
-        }
+#[test]
+fn test_paragraphs() {
+    for strategy in &[
+        Strategy::WordsLinesLongest,
+        Strategy::CharsWordsLinesLongest,
+        Strategy::UnicodeWordsCharsLinesLongest,
+        Strategy::GraphemesCharsWordsLinesLongest,
+    ] {
+        let mut c = Counts::default();
+        strategy
+            .count(Cursor::new(b"a\n\nb\nc\n\n\nd\n"), &mut c, &Opt::default())
+            .unwrap();
+        assert_eq!(c.paragraphs, 3);
+
+        let mut c = Counts::default();
+        strategy
+            .count(Cursor::new(b"\n \na\n\t\nb"), &mut c, &Opt::default())
+            .unwrap();
+        assert_eq!(c.paragraphs, 2);
     }
-
-    // Fast path for -l
-    fn_count!(|| |buf: &[u8], count: &mut Counts| {
-        count.lines += bytecount::count(&buf, b'\n') as u64;
-    });
 }
 
 #[test]
-fn test_lines() {
+fn test_longest_word() {
+    let input = "hi superlongword x";
+
     let mut c = Counts::default();
-    LinesOnly
-        .count(Cursor::new(b"\n\n\n\n\n\n\n\n"), &mut c, &Opt::default())
+    WordsLinesLongest
+        .count(Cursor::new(input), &mut c, &Opt::default())
         .unwrap();
-    assert_eq!(c.lines, 8);
+    assert_eq!(c.longest_word, 13);
+
+    let mut c = Counts::default();
+    CharsWordsLinesLongest
+        .count(
+            Cursor::new("h\u{e9}llo hi\nsuperlongword"),
+            &mut c,
+            &Opt::default(),
+        )
+        .unwrap();
+    assert_eq!(c.longest_word, 13);
+
+    let mut c = Counts::default();
+    UnicodeWordsCharsLinesLongest
+        .count(Cursor::new(input), &mut c, &Opt::default())
+        .unwrap();
+    assert_eq!(c.longest_word, 13);
+
+    let mut c = Counts::default();
+    WordsLinesLongest
+        .count(Cursor::new("h\u{e9}llo"), &mut c, &Opt::default())
+        .unwrap();
+    assert_eq!(c.longest_word, 5);
 }
 
-struct CharsOnly;
-impl Counter for CharsOnly {
-    fn capabilities(&self) -> Capability {
-        Capability {
-            rank: 1,
-            bytes: true,
-            chars: true,
-            ..Capability::default()
+#[test]
+fn test_max_words_per_line() {
+    let opt = Opt {
+        max_words_per_line: true,
+        ..Opt::default()
+    };
+    assert!(matches!(
+        Strategy::try_from(&opt),
+        Ok(Strategy::WordsLinesLongest)
+    ));
+
+    // The last line counts without a newline, and words don't run on across lines
+    for input in &["one two\n\nthree four five\nsix\n", "a\nb c\none two three"] {
+        for strategy in &[
+            Strategy::WordsLinesLongest,
+            Strategy::CharsWordsLinesLongest,
+            Strategy::GraphemesCharsWordsLinesLongest,
+        ] {
+            let mut c = Counts::default();
+            strategy
+                .count(Cursor::new(input.as_bytes()), &mut c, &opt)
+                .unwrap();
+            assert_eq!(c.max_words_per_line, 3, "{:?} {:?}", strategy, input);
         }
     }
 
-    // Fast path for -m
-    fn_count!(|| |buf: &[u8], count: &mut Counts| {
-        count.chars += bytecount::num_chars(&buf) as u64;
-    });
+    // A non-breaking space between words is only caught up with once complete
+    let mut c = Counts::default();
+    WordsLinesLongest
+        .count(Cursor::new("one\u{a0}two\nx\n".as_bytes()), &mut c, &opt)
+        .unwrap();
+    assert_eq!(c.max_words_per_line, 2);
 }
 
 #[test]
-fn test_chars() {
+fn test_blank_lines() {
     let mut c = Counts::default();
-    CharsOnly
-        .count(Cursor::new(b"fo\xC3\xB3"), &mut c, &Opt::default())
+    WordsLinesLongest
+        .count(Cursor::new(b"\n\n x\n"), &mut c, &Opt::default())
         .unwrap();
-    assert_eq!(c.chars, 3);
-    assert_eq!(c.bytes, 4);
+    assert_eq!(c.blank_lines, 2);
+
+    let mut c = Counts::default();
+    CharsWordsLinesLongest
+        .count(Cursor::new(b"\n \xC2\xA0\n x\n"), &mut c, &Opt::default())
+        .unwrap();
+    assert_eq!(c.blank_lines, 2);
 }
 
-struct LinesLongest;
-impl Counter for LinesLongest {
+struct CharsLinesLongest;
+impl Counter for CharsLinesLongest {
     fn capabilities(&self) -> Capability {
         Capability {
-            rank: 30,
+            rank: 120,
             bytes: true,
+            chars: true,
             lines: true,
             longest_line: true,
             ..Capability::default()
         }
     }
 
-    // Fast path for -lL
-    fn_count!(|| {
+    // Fast path for -mlL
+    fn_count!(|opt: &Opt| {
+        let delim = line_delimiter(opt);
+        let tab_width = opt.tab_width;
+        let crlf = crlf(opt);
+        let count_partial = opt.count_partial_line;
         let mut line_len = 0_u64;
+        let mut tail_cr = false;
 
         move |buf: &[u8], count: &mut Counts| {
-            let mut last_pos = 0;
-            for pos in memchr_iter(b'\n', buf) {
-                line_len += ((pos - last_pos as usize) - 1) as u64;
-
-                if count.longest_line < line_len {
-                    count.longest_line = line_len;
-                }
+            // An empty buffer marks the end of the input
+            if buf.is_empty() {
+                count.end_partial_line(line_len, count_partial);
+                return;
+            }
+            // http://canonical.org/~kragen/strlen-utf8
+            //
+            // Counting bytes that don't start 0b10
+            for (i, b) in buf.iter().enumerate() {
+                if (b & 0xc0) != 0x80 {
+                    count.chars += 1;
 
-                line_len = 0;
+                    if *b == delim {
+                        if crlf && cr_before(buf, i, tail_cr) {
+                            line_len -= 1;
+                        }
 
-                count.lines += 1;
-                last_pos = pos as u64;
+                        count.end_line(line_len);
+                        line_len = 0;
+                        count.lines += 1;
+                    } else if *b == b'\t' {
+                        line_len = tab_stop(line_len, tab_width);
+                    } else {
+                        line_len += 1;
+                    }
+                }
             }
 
-            line_len = (buf.len() - last_pos as usize) as u64;
+            tail_cr = buf.last() == Some(&b'\r');
         }
     });
 }
 
 #[test]
-fn test_lines_longest() {
+fn test_chars_lines_longest() {
     let mut c = Counts::default();
-    LinesLongest
+    CharsLinesLongest
         .count(
-            Cursor::new(b"foo\nbar\nmoooo\nhmm\n"),
+            Cursor::new(b"foo\nbar\nmoo\xC3\xB3o\nhmm\n"),
             &mut c,
             &Opt::default(),
         )
         .unwrap();
     assert_eq!(c.lines, 4);
+    assert_eq!(c.chars, c.bytes - 1);
     assert_eq!(c.longest_line, 5);
 }
 
-struct WordsLinesLongest;
-impl Counter for WordsLinesLongest {
+struct CharsWordsLinesLongest;
+impl Counter for CharsWordsLinesLongest {
     fn capabilities(&self) -> Capability {
         Capability {
-            rank: 150,
+            rank: 400,
             words: true,
+            longest_word: true,
+            max_words_per_line: true,
             bytes: true,
+            chars: true,
             lines: true,
             longest_line: true,
+            blank_lines: true,
+            paragraphs: true,
             ..Capability::default()
         }
     }
 
-    // Simple ASCII word count
-    fn_count!(|| {
+    fn count<R: Read>(&self, r: R, count: &mut Counts, opt: &Opt) -> io::Result<()> {
+        let read_size = read_size(opt);
+        let mut reader = BufReader::with_capacity(read_size, InterruptReader::new(r));
+        let delim = line_delimiter(opt);
+
         let mut line_len = 0_u64;
         let mut in_word = false;
+        let mut word_len = 0_u64;
+        let mut line_words = 0_u64;
+        let mut blank = true;
+        let mut prev_blank = true;
+        let mut tail_cr = false;
 
-        move |buf: &[u8], count: &mut Counts| {
-            for b in buf {
-                if (*b as char).is_ascii_whitespace() {
+        // Lines are useful sync points for multibyte reading
+        // Could do with a mbrtowc() workalike really.
+        //
+        // We limit reads to --buffer-size to place an upper-bound on memory use.
+        let mut buf = Vec::with_capacity(read_size);
+        while reader
+            .by_ref()
+            .take(read_size as u64)
+            .read_until(delim, &mut buf)?
+            > 0
+        {
+            // Don't split a character if the read was cut short
+            let needed = utf8_bytes_needed(&buf);
+            if needed > 0 {
+                reader
+                    .by_ref()
+                    .take(needed as u64)
+                    .read_until(delim, &mut buf)?;
+            }
+
+            count.bytes += buf.len() as u64;
+            for (start, end, c) in buf.char_indices() {
+                let chars = utf8_chars(&buf[start..end]);
+                count.chars += chars;
+                if c == delim as char || c.is_whitespace() {
                     in_word = false;
 
-                    if *b == b'\n' {
-                        if count.longest_line < line_len {
-                            count.longest_line = line_len
+                    if c == delim as char {
+                        if crlf(opt) && cr_before(&buf, buf.len() - 1, tail_cr) {
+                            line_len -= 1;
+                        }
+
+                        count.end_line(line_len);
+                        count.max_words_per_line = count.max_words_per_line.max(line_words);
+                        line_words = 0;
+
+                        if blank {
+                            count.blank_lines += 1;
                         }
 
+                        prev_blank = blank;
+
+                        blank = true;
                         line_len = 0;
                         count.lines += 1;
+                    } else if c == '\t' {
+                        line_len = tab_stop(line_len, opt.tab_width);
                     } else {
-                        line_len += 1;
+                        line_len += chars;
                     }
                 } else {
                     if !in_word {
                         count.words += 1;
+                        line_words += 1;
+                        word_len = 0;
                     }
+                    word_len += chars;
+                    count.longest_word = count.longest_word.max(word_len);
                     in_word = true;
-                    line_len += 1;
+                    if blank && prev_blank {
+                        count.paragraphs += 1;
+                    }
+                    blank = false;
+                    line_len += chars;
                 }
             }
+            tail_cr = buf.last() == Some(&b'\r');
+            buf.clear();
+
+            check_progress(count, opt)?;
         }
-    });
+
+        count.end_partial_line(line_len, opt.count_partial_line);
+        count.max_words_per_line = count.max_words_per_line.max(line_words);
+        Ok(())
+    }
 }
 
 #[test]
-fn test_words_lines_longest() {
+fn test_chars_words_lines_longest() {
     let mut c = Counts::default();
-    WordsLinesLongest
+    CharsWordsLinesLongest
         .count(
-            Cursor::new(b"one two\nthree\nfour five six\n"),
+            Cursor::new(b"\xC3\xB3ne two\nthree\nfour five six\n"),
             &mut c,
             &Opt::default(),
         )
         .unwrap();
     assert_eq!(c.lines, 3);
     assert_eq!(c.words, 6);
+    assert_eq!(c.chars, c.bytes - 1);
     assert_eq!(c.longest_line, 13);
 }
 
-struct CharsLinesLongest;
-impl Counter for CharsLinesLongest {
+struct UnicodeWordsCharsLinesLongest;
+impl Counter for UnicodeWordsCharsLinesLongest {
+    // UAX#29 word boundaries for --unicode-words
     fn capabilities(&self) -> Capability {
         Capability {
-            rank: 120,
+            rank: 450,
+            words: true,
+            longest_word: true,
             bytes: true,
             chars: true,
             lines: true,
             longest_line: true,
+            blank_lines: true,
+            paragraphs: true,
+            unicode_words: true,
+            any_unit: true,
             ..Capability::default()
         }
     }
 
-    // Fast path for -mlL
-    fn_count!(|| {
-        let mut last_chars = 0;
+    fn count<R: Read>(&self, r: R, count: &mut Counts, opt: &Opt) -> io::Result<()> {
+        let read_size = read_size(opt);
+        let mut reader = BufReader::with_capacity(read_size, InterruptReader::new(r));
+        let delim = line_delimiter(opt);
 
-        move |buf: &[u8], count: &mut Counts| {
-            // http://canonical.org/~kragen/strlen-utf8
-            //
-            // Counting bytes that don't start 0b10
-            for b in buf {
-                if (b & 0xc0) != 0x80 {
-                    count.chars += 1;
+        let mut line_len = 0_u64;
+        let mut blank = true;
+        let mut prev_blank = true;
+        let mut tail_cr = false;
+
+        // Word boundaries never span a newline, so read line by line
+        let mut buf = Vec::with_capacity(read_size);
+        while reader
+            .by_ref()
+            .take(read_size as u64)
+            .read_until(delim, &mut buf)?
+            > 0
+        {
+            // Don't split a character if the read was cut short
+            let needed = utf8_bytes_needed(&buf);
+            if needed > 0 {
+                reader
+                    .by_ref()
+                    .take(needed as u64)
+                    .read_until(delim, &mut buf)?;
+            }
 
-                    if *b == b'\n' {
-                        let line_len = (count.chars - last_chars) - 1;
-                        last_chars = count.chars;
+            count.bytes += buf.len() as u64;
+            for word in buf.to_str_lossy().unicode_words() {
+                count.words += 1;
+                count.longest_word = count.longest_word.max(word.chars().count() as u64);
+            }
 
-                        if count.longest_line < line_len {
-                            count.longest_line = line_len
-                        }
-                        count.lines += 1;
+            for (start, end, c) in buf.char_indices() {
+                let chars = utf8_chars(&buf[start..end]);
+                count.chars += chars;
+
+                if c == delim as char {
+                    if crlf(opt) && cr_before(&buf, buf.len() - 1, tail_cr) {
+                        line_len -= 1;
+                    }
+
+                    count.end_line(line_len);
+
+                    if blank {
+                        count.blank_lines += 1;
+                    }
+
+                    prev_blank = blank;
+
+                    blank = true;
+                    line_len = 0;
+                    count.lines += 1;
+                    continue;
+                }
+
+                if !c.is_whitespace() {
+                    if blank && prev_blank {
+                        count.paragraphs += 1;
                     }
+                    blank = false;
+                }
+
+                if c == '\t' {
+                    line_len = tab_stop(line_len, opt.tab_width);
+                } else if opt.chars {
+                    line_len += chars;
+                } else {
+                    line_len += (end - start) as u64;
                 }
             }
+            tail_cr = buf.last() == Some(&b'\r');
+            buf.clear();
+
+            check_progress(count, opt)?;
         }
-    });
+
+        count.end_partial_line(line_len, opt.count_partial_line);
+        Ok(())
+    }
 }
 
 #[test]
-fn test_chars_lines_longest() {
+fn test_unicode_words_chars_lines_longest() {
     let mut c = Counts::default();
-    CharsLinesLongest
+    UnicodeWordsCharsLinesLongest
         .count(
-            Cursor::new(b"foo\nbar\nmoo\xC3\xB3o\nhmm\n"),
+            Cursor::new(
+                "The quick (\"brown\") fox\n\u{65e5}\u{672c}\u{8a9e} text\u{a0}here\n".as_bytes(),
+            ),
             &mut c,
             &Opt::default(),
         )
         .unwrap();
-    assert_eq!(c.lines, 4);
-    assert_eq!(c.chars, c.bytes - 1);
-    assert_eq!(c.longest_line, 5);
+    assert_eq!(c.lines, 2);
+    assert_eq!(c.words, 9);
 }
 
-struct CharsWordsLinesLongest;
-impl Counter for CharsWordsLinesLongest {
+struct GraphemesCharsWordsLinesLongest;
+impl Counter for GraphemesCharsWordsLinesLongest {
+    // Grapheme segmentation is the slowest thing we do, so it's only ever
+    // picked for --graphemes.
     fn capabilities(&self) -> Capability {
         Capability {
-            rank: 400,
+            rank: 500,
             words: true,
+            longest_word: true,
+            max_words_per_line: true,
             bytes: true,
             chars: true,
             lines: true,
             longest_line: true,
+            blank_lines: true,
+            paragraphs: true,
+            graphemes: true,
+            any_unit: true,
+            ..Capability::default()
         }
     }
 
     fn count<R: Read>(&self, r: R, count: &mut Counts, opt: &Opt) -> io::Result<()> {
-        let mut reader = BufReader::with_capacity(READ_SIZE, r);
+        let read_size = read_size(opt);
+        let mut reader = BufReader::with_capacity(read_size, InterruptReader::new(r));
+        let delim = line_delimiter(opt);
 
         let mut line_len = 0_u64;
         let mut in_word = false;
-
-        // Lines are useful sync points for multibyte reading
-        // Could do with a mbrtowc() workalike really.
-        //
-        // We limit reads to READ_SIZE to place an upper-bound on memory use.
-        let mut buf = Vec::with_capacity(READ_SIZE);
+        let mut word_len = 0_u64;
+        let mut line_words = 0_u64;
+        let mut blank = true;
+        let mut prev_blank = true;
+        let mut tail_cr = false;
+
+        // Grapheme clusters never span a newline, besides the \r\n pair
+        // that read_until() keeps together anyway.
+        let mut buf = Vec::with_capacity(read_size);
         while reader
             .by_ref()
-            .take(READ_SIZE as u64)
-            .read_until(b'\n', &mut buf)?
+            .take(read_size as u64)
+            .read_until(delim, &mut buf)?
             > 0
         {
+            // Don't split a character if the read was cut short
+            let needed = utf8_bytes_needed(&buf);
+            if needed > 0 {
+                reader
+                    .by_ref()
+                    .take(needed as u64)
+                    .read_until(delim, &mut buf)?;
+            }
+
             count.bytes += buf.len() as u64;
-            for c in buf.chars() {
-                count.chars += 1;
-                if c.is_whitespace() {
+            count.graphemes += buf.to_str_lossy().graphemes(true).count() as u64;
+
+            for (start, end, c) in buf.char_indices() {
+                let chars = utf8_chars(&buf[start..end]);
+                count.chars += chars;
+                let width = if opt.chars {
+                    chars
+                } else {
+                    (end - start) as u64
+                };
+
+                if c == delim as char || c.is_whitespace() {
                     in_word = false;
 
-                    if c == '\n' {
-                        if count.longest_line < line_len {
-                            count.longest_line = line_len
+                    if c == delim as char {
+                        if crlf(opt) && cr_before(&buf, buf.len() - 1, tail_cr) {
+                            line_len -= 1;
+                        }
+
+                        count.end_line(line_len);
+                        count.max_words_per_line = count.max_words_per_line.max(line_words);
+                        line_words = 0;
+
+                        if blank {
+                            count.blank_lines += 1;
                         }
 
+                        prev_blank = blank;
+
+                        blank = true;
                         line_len = 0;
                         count.lines += 1;
+                    } else if c == '\t' {
+                        line_len = tab_stop(line_len, opt.tab_width);
                     } else {
-                        line_len += 1;
+                        line_len += width;
                     }
                 } else {
                     if !in_word {
                         count.words += 1;
+                        line_words += 1;
+                        word_len = 0;
                     }
+                    word_len += chars;
+                    count.longest_word = count.longest_word.max(word_len);
                     in_word = true;
-                    line_len += 1;
+                    if blank && prev_blank {
+                        count.paragraphs += 1;
+                    }
+                    blank = false;
+                    line_len += width;
                 }
             }
+            tail_cr = buf.last() == Some(&b'\r');
             buf.clear();
 
-            if siginfo::check_signal() {
-                let err = io::stderr();
-                let mut errl = err.lock();
-                let _ = count.print(&opt, &mut errl);
-            }
+            check_progress(count, opt)?;
         }
 
+        count.end_partial_line(line_len, opt.count_partial_line);
+        count.max_words_per_line = count.max_words_per_line.max(line_words);
         Ok(())
     }
 }
 
 #[test]
-fn test_chars_words_lines_longest() {
+fn test_graphemes_chars_words_lines_longest() {
     let mut c = Counts::default();
-    CharsWordsLinesLongest
+    GraphemesCharsWordsLinesLongest
         .count(
-            Cursor::new(b"\xC3\xB3ne two\nthree\nfour five six\n"),
+            Cursor::new("e\u{301}t\u{e9} \u{1F469}\u{200D}\u{1F4BB}\n".as_bytes()),
             &mut c,
             &Opt::default(),
         )
         .unwrap();
-    assert_eq!(c.lines, 3);
-    assert_eq!(c.words, 6);
-    assert_eq!(c.chars, c.bytes - 1);
-    assert_eq!(c.longest_line, 13);
+    assert_eq!(c.lines, 1);
+    assert_eq!(c.words, 2);
+    assert_eq!(c.chars, 9);
+    assert_eq!(c.graphemes, 6);
+}
+
+#[test]
+fn test_count_parallel() {
+    let input = "one two\n\nthree\u{f3} four\n".repeat(PARALLEL_CHUNK / 8);
+    let opt = Opt {
+        lines: true,
+        bytes: true,
+        count_byte: vec![b'o'],
+        record_bytes: std::num::NonZeroU64::new(1000),
+        ..Opt::default()
+    };
+
+    for strategy in &[Strategy::LinesOnly, Strategy::BytesOnly] {
+        assert!(strategy.is_parallel(&opt));
+
+        let mut expected = Counts::default();
+        strategy
+            .count(Cursor::new(&input), &mut expected, &opt)
+            .unwrap();
+
+        for threads in 1..4 {
+            let mut c = Counts::default();
+            strategy
+                .count_parallel(Cursor::new(&input), &mut c, &opt, threads)
+                .unwrap();
+            let context = format!("{:?} with {} threads", strategy, threads);
+            assert_eq!(c.lines, expected.lines, "{}", context);
+            assert_eq!(c.bytes, expected.bytes, "{}", context);
+            assert_eq!(c.byte_matches, expected.byte_matches, "{}", context);
+            assert_eq!(c.records, expected.records, "{}", context);
+        }
+    }
+
+    let words = Opt {
+        words: true,
+        ..Opt::default()
+    };
+    assert!(!Strategy::try_from(&words).unwrap().is_parallel(&words));
+}
+
+#[test]
+fn test_explain() {
+    let opt = Opt {
+        lines: true,
+        words: true,
+        ..Opt::default()
+    };
+
+    let mut out = vec![];
+    Strategy::explain(&opt, &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+
+    assert!(out.starts_with("chosen: WordsLinesLongest\n"), "{}", out);
+    assert!(out.contains("\nLinesOnly, rank 1\n  counts: lines bytes\n  lacks: words\n"));
+    assert_eq!(out.matches("\n\n").count(), Strategy::ALL.len());
+
+    let mut out = vec![];
+    let unsupported = Opt {
+        chars: true,
+        words: true,
+        word_separators: Some(ByteSet::ascii_whitespace()),
+        ..Opt::default()
+    };
+    Strategy::explain(&unsupported, &mut out).unwrap();
+    assert!(out.starts_with(b"chosen: none"));
+}
+
+#[test]
+fn test_forced_strategy() {
+    assert!(matches!(
+        "charswordslineslongest".parse(),
+        Ok(Strategy::CharsWordsLinesLongest)
+    ));
+    assert!("Nope".parse::<Strategy>().is_err());
+
+    let opt = Opt {
+        lines: true,
+        strategy: Some(Strategy::GraphemesCharsWordsLinesLongest),
+        ..Opt::default()
+    };
+    assert!(matches!(
+        Strategy::try_from(&opt),
+        Ok(Strategy::GraphemesCharsWordsLinesLongest)
+    ));
+
+    let opt = Opt {
+        words: true,
+        strategy: Some(Strategy::LinesOnly),
+        ..Opt::default()
+    };
+    assert!(Strategy::try_from(&opt).is_err());
+    assert_eq!(Strategy::LinesOnly.lacks(&opt), ["words"]);
 }
@@ -0,0 +1,86 @@
+use std::io::{self, Read};
+
+use encoding_rs::{Decoder, Encoding};
+
+const READ_SIZE: usize = 1024 * 32;
+
+/// Parse an `--encoding` label such as utf-16le or latin1.
+///
+/// Labels are as defined by the WHATWG Encoding Standard, so latin1 is
+/// windows-1252.
+pub fn parse_encoding(label: &str) -> Result<&'static Encoding, String> {
+    Encoding::for_label(label.as_bytes()).ok_or_else(|| format!("unknown encoding {}", label))
+}
+
+/// Transcodes a reader to UTF-8, keeping track of how many bytes of the
+/// original input have been consumed.
+pub struct DecodeReader<R> {
+    inner: R,
+    decoder: Decoder,
+    raw: Vec<u8>,
+    decoded: Vec<u8>,
+    pos: usize,
+    done: bool,
+    pub raw_bytes: u64,
+}
+
+impl<R: Read> DecodeReader<R> {
+    pub fn new(inner: R, encoding: &'static Encoding) -> Self {
+        Self {
+            inner,
+            decoder: encoding.new_decoder(),
+            raw: vec![0; READ_SIZE],
+            decoded: vec![],
+            pos: 0,
+            done: false,
+            raw_bytes: 0,
+        }
+    }
+
+    fn refill(&mut self) -> io::Result<()> {
+        let len = self.inner.read(&mut self.raw)?;
+        self.raw_bytes += len as u64;
+        self.done = len == 0;
+
+        let max = self
+            .decoder
+            .max_utf8_buffer_length(len)
+            .expect("decode buffer overflow");
+        self.decoded.resize(max, 0);
+
+        let (_, read, written, _) =
+            self.decoder
+                .decode_to_utf8(&self.raw[..len], &mut self.decoded, self.done);
+        debug_assert_eq!(read, len);
+
+        self.decoded.truncate(written);
+        self.pos = 0;
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for DecodeReader<R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.decoded.len() {
+            if self.done {
+                return Ok(0);
+            }
+            self.refill()?;
+        }
+
+        let len = std::cmp::min(out.len(), self.decoded.len() - self.pos);
+        out[..len].copy_from_slice(&self.decoded[self.pos..self.pos + len]);
+        self.pos += len;
+        Ok(len)
+    }
+}
+
+#[test]
+fn test_decode_utf16le() {
+    let input = b"\xFF\xFEa\x00\xF3\x00\n\x00";
+    let mut reader = DecodeReader::new(&input[..], encoding_rs::UTF_16LE);
+    let mut out = String::new();
+    reader.read_to_string(&mut out).unwrap();
+    assert_eq!(out, "a\u{f3}\n");
+    assert_eq!(reader.raw_bytes, 8);
+}
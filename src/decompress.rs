@@ -0,0 +1,58 @@
+use std::io::{self, Cursor, Read};
+
+const GZIP_MAGIC: &[u8] = b"\x1f\x8b";
+const ZSTD_MAGIC: &[u8] = b"\x28\xb5\x2f\xfd";
+
+/// Wrap a reader in a decoder if it starts with gzip or zstd magic bytes,
+/// otherwise passing it through as it is.
+///
+/// Without the `decompress` feature, input is always passed through.
+pub fn decompress<'a, R: Read + 'a>(mut r: R) -> io::Result<Box<dyn Read + 'a>> {
+    let mut magic = Vec::with_capacity(ZSTD_MAGIC.len());
+    r.by_ref()
+        .take(ZSTD_MAGIC.len() as u64)
+        .read_to_end(&mut magic)?;
+
+    let gzip = magic.starts_with(GZIP_MAGIC);
+    let zstd = magic.starts_with(ZSTD_MAGIC);
+    let r = Cursor::new(magic).chain(r);
+
+    #[cfg(feature = "decompress")]
+    {
+        if gzip {
+            return Ok(Box::new(flate2::read::MultiGzDecoder::new(r)));
+        } else if zstd {
+            return Ok(Box::new(zstd::stream::read::Decoder::new(r)?));
+        }
+    }
+
+    #[cfg(not(feature = "decompress"))]
+    let _ = (gzip, zstd);
+
+    Ok(Box::new(r))
+}
+
+#[cfg(feature = "decompress")]
+#[test]
+fn test_decompress() {
+    use std::io::Write;
+
+    let text = b"one two\nthree\n";
+
+    let mut gz = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+    gz.write_all(text).unwrap();
+    let gz = gz.finish().unwrap();
+
+    let zst = zstd::stream::encode_all(&text[..], 0).unwrap();
+
+    for input in &[&gz[..], &zst[..], &text[..], &b"a"[..]] {
+        let mut out = vec![];
+        decompress(*input).unwrap().read_to_end(&mut out).unwrap();
+        let expected = if input.len() == 1 {
+            &b"a"[..]
+        } else {
+            &text[..]
+        };
+        assert_eq!(out, expected);
+    }
+}
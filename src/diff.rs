@@ -0,0 +1,224 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::Path;
+
+use crate::args::{Opt, Total};
+use crate::count::open_file;
+
+const READ_SIZE: usize = 1024 * 32;
+
+/// Lines added and removed in one file of a diff
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct FileDiff {
+    /// The path from the `+++` header, or `---` for a deleted file
+    pub path: String,
+    pub added: u64,
+    pub removed: u64,
+}
+
+/// Lines added and removed by each file of unified diffs, for `--diff`.
+///
+/// Only lines within hunks are counted, as given by their `@@` headers, so
+/// context, file headers and anything else around them is ignored.
+#[derive(Debug, Default)]
+pub struct Diff {
+    files: Vec<FileDiff>,
+    // The `---` path, until its `+++` line starts a file
+    old_path: Option<String>,
+    // Lines left in the current hunk, on the old and new sides
+    old_left: u64,
+    new_left: u64,
+}
+
+impl Diff {
+    pub fn count<R: Read>(&mut self, r: R) -> io::Result<()> {
+        let mut reader = BufReader::with_capacity(READ_SIZE, r);
+        let mut line = Vec::new();
+
+        while reader.read_until(b'\n', &mut line)? > 0 {
+            let text = line.strip_suffix(b"\n").unwrap_or(&line);
+            let text = text.strip_suffix(b"\r").unwrap_or(text);
+            self.line(&String::from_utf8_lossy(text));
+            line.clear();
+        }
+
+        // A diff truncated mid-hunk mustn't leave the next one's headers in it
+        self.old_left = 0;
+        self.new_left = 0;
+        Ok(())
+    }
+
+    pub fn count_file<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        self.count(open_file(path)?)
+    }
+
+    fn line(&mut self, line: &str) {
+        if self.old_left > 0 || self.new_left > 0 {
+            return self.hunk_line(line);
+        }
+
+        if let Some(path) = line.strip_prefix("--- ") {
+            self.old_path = Some(header_path(path));
+        } else if let (Some(path), Some(old)) = (line.strip_prefix("+++ "), self.old_path.take()) {
+            let new = header_path(path);
+            // git prefixes the two sides with a/ and b/, with /dev/null for a missing side
+            let git = (old.starts_with("a/") || old == "/dev/null")
+                && (new.starts_with("b/") || new == "/dev/null");
+            let path = if new == "/dev/null" { old } else { new };
+
+            self.files.push(FileDiff {
+                path: match path.get(2..) {
+                    Some(stripped) if git && path != "/dev/null" => stripped.to_string(),
+                    _ => path,
+                },
+                ..FileDiff::default()
+            });
+        } else if let Some(range) = line.strip_prefix("@@ -") {
+            let mut ranges = range.split(' ');
+            self.old_left = hunk_length(ranges.next());
+            self.new_left = ranges
+                .next()
+                .and_then(|r| r.strip_prefix('+'))
+                .map_or(0, |r| hunk_length(Some(r)));
+
+            // Hunks before any file header still need somewhere to go
+            if self.files.is_empty() {
+                self.files.push(FileDiff::default());
+            }
+        }
+    }
+
+    fn hunk_line(&mut self, line: &str) {
+        let file = self.files.last_mut().expect("hunk without a file");
+
+        match line.bytes().next() {
+            Some(b'+') => {
+                file.added += 1;
+                self.new_left = self.new_left.saturating_sub(1);
+            }
+            Some(b'-') => {
+                file.removed += 1;
+                self.old_left = self.old_left.saturating_sub(1);
+            }
+            // A note such as "\ No newline at end of file"
+            Some(b'\\') => (),
+            _ => {
+                self.old_left = self.old_left.saturating_sub(1);
+                self.new_left = self.new_left.saturating_sub(1);
+            }
+        }
+    }
+
+    /// Each file in the order it appeared
+    pub fn files(&self) -> &[FileDiff] {
+        &self.files
+    }
+
+    /// The lines added and removed across every file
+    pub fn total(&self) -> FileDiff {
+        self.files.iter().fold(
+            FileDiff {
+                path: "total".to_string(),
+                ..FileDiff::default()
+            },
+            |mut total, file| {
+                total.added += file.added;
+                total.removed += file.removed;
+                total
+            },
+        )
+    }
+
+    /// Print a row of lines added and removed for each file, with a total
+    /// following them as `--total` asks
+    pub fn print<W: Write>(&self, opt: &Opt, mut out: W) -> io::Result<()> {
+        let print_total = match opt.total {
+            Total::Auto => self.files.len() > 1,
+            Total::Always | Total::Only => true,
+            Total::Never => false,
+        };
+
+        let mut rows: Vec<&FileDiff> = vec![];
+        if opt.total != Total::Only {
+            rows.extend(&self.files);
+        }
+        let total = self.total();
+        if print_total {
+            rows.push(&total);
+        }
+
+        for row in rows {
+            writeln!(
+                &mut out,
+                " {:>7} {:>7} {}",
+                row.added, row.removed, row.path
+            )?;
+        }
+
+        out.flush()
+    }
+}
+
+// The path of a `---` or `+++` header, without any timestamp following a tab
+fn header_path(header: &str) -> String {
+    header.split('\t').next().unwrap_or(header).to_string()
+}
+
+// The number of lines in one side of a hunk header range such as `12,3`,
+// which is 1 if the length is left out
+fn hunk_length(range: Option<&str>) -> u64 {
+    match range.and_then(|r| r.split_once(',')) {
+        Some((_, len)) => len.parse().unwrap_or(0),
+        None => 1,
+    }
+}
+
+#[test]
+fn test_diff() {
+    let input = b"diff --git a/src/foo.rs b/src/foo.rs
+index 1234567..89abcde 100644
+--- a/src/foo.rs
++++ b/src/foo.rs
+@@ -1,3 +1,4 @@
+ fn main() {
+-    old();
++    new();
++    newer();
+ }
+@@ -10 +11 @@ fn other
+--- not a header
++++ nor this
+diff --git a/gone.txt b/gone.txt
+deleted file mode 100644
+--- a/gone.txt
++++ /dev/null
+@@ -1,2 +0,0 @@
+-one
+-two
+\\ No newline at end of file
+";
+
+    let mut diff = Diff::default();
+    diff.count(&input[..]).unwrap();
+    assert_eq!(
+        diff.files(),
+        [
+            FileDiff {
+                path: "src/foo.rs".to_string(),
+                added: 3,
+                removed: 2,
+            },
+            FileDiff {
+                path: "gone.txt".to_string(),
+                added: 0,
+                removed: 2,
+            },
+        ]
+    );
+
+    let mut out = vec![];
+    diff.print(&Opt::default(), &mut out).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "       3       2 src/foo.rs\n       0       2 gone.txt\n       3       4 total\n"
+    );
+}
@@ -0,0 +1,340 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+
+use memchr::memchr_iter;
+use regex::bytes::Regex;
+
+use crate::count::Interrupted;
+use crate::siginfo;
+
+const READ_SIZE: usize = 1024 * 32;
+
+/// Passes through only the lines of a reader that match a regex, or with
+/// `invert`, only those that don't.
+///
+/// Lines end in `delimiter`, and are matched without it.  Input is read a
+/// line at a time, so this is slower than counting in fixed-size chunks, and
+/// a single very long line is held in memory in its entirety.
+pub struct MatchReader<R> {
+    inner: BufReader<R>,
+    regex: Regex,
+    invert: bool,
+    delimiter: u8,
+    line: Vec<u8>,
+    pos: usize,
+}
+
+impl<R: Read> MatchReader<R> {
+    pub fn new(inner: R, regex: Regex, invert: bool, delimiter: u8) -> Self {
+        Self {
+            inner: BufReader::with_capacity(READ_SIZE, inner),
+            regex,
+            invert,
+            delimiter,
+            line: vec![],
+            pos: 0,
+        }
+    }
+}
+
+impl<R: Read> Read for MatchReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos >= self.line.len() {
+            self.line.clear();
+            self.pos = 0;
+
+            if self.inner.read_until(self.delimiter, &mut self.line)? == 0 {
+                return Ok(0);
+            }
+
+            let text = self
+                .line
+                .strip_suffix(&[self.delimiter])
+                .unwrap_or(&self.line);
+            if self.regex.is_match(text) == self.invert {
+                self.line.clear();
+            }
+        }
+
+        let len = std::cmp::min(buf.len(), self.line.len() - self.pos);
+        buf[..len].copy_from_slice(&self.line[self.pos..self.pos + len]);
+        self.pos += len;
+        Ok(len)
+    }
+}
+
+#[test]
+fn test_match_reader() {
+    let input = &b"foo\nbar\nfood\nbaz"[..];
+
+    let mut out = String::new();
+    MatchReader::new(input, Regex::new("^foo").unwrap(), false, b'\n')
+        .read_to_string(&mut out)
+        .unwrap();
+    assert_eq!(out, "foo\nfood\n");
+
+    let mut out = String::new();
+    MatchReader::new(input, Regex::new("^foo").unwrap(), true, b'\n')
+        .read_to_string(&mut out)
+        .unwrap();
+    assert_eq!(out, "bar\nbaz");
+
+    let mut out = String::new();
+    MatchReader::new(&b"foo\0bar\0food"[..], Regex::new("o$").unwrap(), false, 0)
+        .read_to_string(&mut out)
+        .unwrap();
+    assert_eq!(out, "foo\0");
+}
+
+/// Passes through a window of a reader's lines, ending in `delimiter`,
+/// skipping the first `skip` lines and stopping after `max` more, if given.
+pub struct WindowReader<R> {
+    inner: BufReader<R>,
+    skip: u64,
+    max: Option<u64>,
+    delimiter: u8,
+}
+
+impl<R: Read> WindowReader<R> {
+    pub fn new(inner: R, skip: u64, max: Option<u64>, delimiter: u8) -> Self {
+        Self {
+            inner: BufReader::with_capacity(READ_SIZE, inner),
+            skip,
+            max,
+            delimiter,
+        }
+    }
+}
+
+impl<R: Read> Read for WindowReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.skip > 0 {
+            let available = self.inner.fill_buf()?;
+            if available.is_empty() {
+                return Ok(0);
+            }
+
+            let mut len = available.len();
+            for pos in memchr_iter(self.delimiter, available) {
+                self.skip -= 1;
+                if self.skip == 0 {
+                    len = pos + 1;
+                    break;
+                }
+            }
+            self.inner.consume(len);
+        }
+
+        if self.max == Some(0) {
+            return Ok(0);
+        }
+
+        let available = self.inner.fill_buf()?;
+        let mut len = std::cmp::min(buf.len(), available.len());
+
+        if let Some(ref mut max) = self.max {
+            for pos in memchr_iter(self.delimiter, &available[..len]) {
+                *max -= 1;
+                if *max == 0 {
+                    len = pos + 1;
+                    break;
+                }
+            }
+        }
+
+        buf[..len].copy_from_slice(&available[..len]);
+        self.inner.consume(len);
+        Ok(len)
+    }
+}
+
+#[test]
+fn test_window_reader() {
+    let input = &b"1\n2\n3\n4\n5"[..];
+    let window = |skip, max| {
+        let mut out = String::new();
+        WindowReader::new(input, skip, max, b'\n')
+            .read_to_string(&mut out)
+            .unwrap();
+        out
+    };
+
+    assert_eq!(window(0, None), "1\n2\n3\n4\n5");
+    assert_eq!(window(1, Some(2)), "2\n3\n");
+    assert_eq!(window(3, None), "4\n5");
+    assert_eq!(window(3, Some(5)), "4\n5");
+    assert_eq!(window(4, Some(1)), "5");
+    assert_eq!(window(5, None), "");
+    assert_eq!(window(0, Some(0)), "");
+
+    let mut out = String::new();
+    WindowReader::new(&b"1\r2\r3\r4"[..], 1, Some(2), b'\r')
+        .read_to_string(&mut out)
+        .unwrap();
+    assert_eq!(out, "2\r3\r");
+}
+
+/// Passes a reader through unchanged, tallying how often each of a set of
+/// bytes occurs in it.
+pub struct ByteMatchReader<'a, R> {
+    inner: R,
+    bytes: &'a [u8],
+    /// Occurrences so far of each byte, in the order given to `new`
+    pub matches: Vec<u64>,
+}
+
+impl<'a, R: Read> ByteMatchReader<'a, R> {
+    pub fn new(inner: R, bytes: &'a [u8]) -> Self {
+        Self {
+            inner,
+            bytes,
+            matches: vec![0; bytes.len()],
+        }
+    }
+}
+
+impl<'a, R: Read> Read for ByteMatchReader<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        for (matches, byte) in self.matches.iter_mut().zip(self.bytes) {
+            *matches += bytecount::count(&buf[..len], *byte) as u64;
+        }
+
+        Ok(len)
+    }
+}
+
+#[test]
+fn test_byte_match_reader() {
+    let mut reader = ByteMatchReader::new(&b"a,b,c\n1,2,3\n"[..], b",\n;");
+    io::copy(&mut reader, &mut io::sink()).unwrap();
+    assert_eq!(reader.matches, [4, 2, 0]);
+}
+
+/// Copies everything read through it to a writer, as `tee` does
+pub struct TeeReader<R, W> {
+    inner: R,
+    out: W,
+}
+
+impl<R: Read, W: Write> TeeReader<R, W> {
+    pub fn new(inner: R, out: W) -> Self {
+        Self { inner, out }
+    }
+
+    /// The writer, to flush once reading is done
+    pub fn writer(&mut self) -> &mut W {
+        &mut self.out
+    }
+}
+
+impl<R: Read, W: Write> Read for TeeReader<R, W> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        self.out.write_all(&buf[..len])?;
+        Ok(len)
+    }
+}
+
+#[test]
+fn test_tee_reader() {
+    let mut copy = vec![];
+    let mut reader = TeeReader::new(&b"foo\nbar\n"[..], &mut copy);
+    let mut first = [0; 4];
+    reader.read_exact(&mut first).unwrap();
+    assert_eq!(&first, b"foo\n");
+    io::copy(&mut reader, &mut io::sink()).unwrap();
+    assert_eq!(copy, b"foo\nbar\n");
+}
+
+/// Passes through a reader's bytes while they're ASCII, failing with an
+/// `InvalidData` error at the first byte that isn't.
+pub struct AsciiReader<R> {
+    inner: R,
+    offset: u64,
+}
+
+impl<R: Read> AsciiReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self { inner, offset: 0 }
+    }
+}
+
+impl<R: Read> Read for AsciiReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        if let Some(pos) = buf[..len].iter().position(|b| !b.is_ascii()) {
+            return Err(non_ascii(buf[pos], self.offset + pos as u64));
+        }
+
+        self.offset += len as u64;
+        Ok(len)
+    }
+}
+
+/// The error for a non-ASCII `byte` found at `offset` with `--ascii`
+pub fn non_ascii(byte: u8, offset: u64) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!(
+            "non-ASCII byte 0x{:02X} at offset {} with --ascii",
+            byte, offset
+        ),
+    )
+}
+
+#[test]
+fn test_ascii_reader() {
+    let mut out = vec![];
+    AsciiReader::new(&b"plain\ttext\n"[..])
+        .read_to_end(&mut out)
+        .unwrap();
+    assert_eq!(out, b"plain\ttext\n");
+
+    let err = AsciiReader::new(&b"caf\xC3\xA9\n"[..])
+        .read_to_end(&mut out)
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert!(err.to_string().contains("0xC3 at offset 3"));
+}
+
+/// Fails with `Interrupted` when a read is cut short by Ctrl-C, rather than
+/// leaving `read_until` and the like to retry it and wait for more input.
+pub struct InterruptReader<R> {
+    inner: R,
+}
+
+impl<R: Read> InterruptReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self { inner }
+    }
+}
+
+impl<R: Read> Read for InterruptReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.inner.read(buf) {
+            Err(e) if e.kind() == io::ErrorKind::Interrupted && siginfo::interrupted() => {
+                Err(Interrupted.into())
+            }
+            ret => ret,
+        }
+    }
+}
+
+#[test]
+fn test_interrupt_reader() {
+    // Interrupted by some other signal, so retried as usual
+    struct Flaky(bool);
+    impl Read for Flaky {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if std::mem::replace(&mut self.0, false) {
+                return Err(io::ErrorKind::Interrupted.into());
+            }
+            (&b""[..]).read(buf)
+        }
+    }
+
+    let mut buf = vec![];
+    let mut reader = BufReader::new(InterruptReader::new(Flaky(true)).chain(&b"a\n"[..]));
+    reader.read_until(b'\n', &mut buf).unwrap();
+    assert_eq!(buf, b"a\n");
+}
@@ -0,0 +1,108 @@
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::Path;
+
+use crate::args::Opt;
+use crate::count::open_file;
+
+const READ_SIZE: usize = 1024 * 32;
+
+/// Word frequencies across any number of inputs, for `--frequency`.
+///
+/// Words are split on ASCII whitespace, as with the plain `-w` count.  Every
+/// distinct word is held in memory, so this is much heavier than counting.
+#[derive(Debug, Default)]
+pub struct Frequency {
+    case_insensitive: bool,
+    words: HashMap<String, u64>,
+}
+
+impl Frequency {
+    pub fn new(opt: &Opt) -> Self {
+        Self {
+            case_insensitive: opt.case_insensitive,
+            words: HashMap::new(),
+        }
+    }
+
+    pub fn count<R: Read>(&mut self, r: R) -> io::Result<()> {
+        let mut reader = BufReader::with_capacity(READ_SIZE, r);
+        let mut word = Vec::new();
+
+        loop {
+            let len = {
+                let buf = reader.fill_buf()?;
+                if buf.is_empty() {
+                    break;
+                }
+
+                for b in buf {
+                    if b.is_ascii_whitespace() {
+                        self.insert(&word);
+                        word.clear();
+                    } else {
+                        word.push(*b);
+                    }
+                }
+
+                buf.len()
+            };
+            reader.consume(len);
+        }
+
+        self.insert(&word);
+        Ok(())
+    }
+
+    pub fn count_file<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        self.count(open_file(path)?)
+    }
+
+    fn insert(&mut self, word: &[u8]) {
+        if word.is_empty() {
+            return;
+        }
+
+        let mut word = String::from_utf8_lossy(word).into_owned();
+        if self.case_insensitive {
+            word = word.to_lowercase();
+        }
+
+        *self.words.entry(word).or_insert(0) += 1;
+    }
+
+    /// The `n` most frequent words, most frequent first, ties broken alphabetically
+    pub fn top(&self, n: usize) -> Vec<(&str, u64)> {
+        let mut words: Vec<_> = self
+            .words
+            .iter()
+            .map(|(word, count)| (word.as_str(), *count))
+            .collect();
+
+        words.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        words.truncate(n);
+        words
+    }
+
+    pub fn print<W: Write>(&self, n: usize, mut out: W) -> io::Result<()> {
+        for (word, count) in self.top(n) {
+            writeln!(&mut out, " {:>7} {}", count, word)?;
+        }
+
+        out.flush()
+    }
+}
+
+#[test]
+fn test_frequency() {
+    let mut f = Frequency::default();
+    f.count(&b"the cat and The dog\nand the\tbird"[..]).unwrap();
+    assert_eq!(f.top(2), vec![("and", 2), ("the", 2)]);
+
+    let mut f = Frequency::new(&Opt {
+        case_insensitive: true,
+        ..Opt::default()
+    });
+    f.count(&b"the cat and The dog\nand the\tbird"[..]).unwrap();
+    assert_eq!(f.top(2), vec![("the", 3), ("and", 2)]);
+}
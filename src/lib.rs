@@ -1,3 +1,138 @@
+//! Count Words, a fast `wc` clone.
+//!
+//! The counting machinery behind the `cw` binary.  Configure an [`Opt`] with
+//! the metrics you want and hand it to [`count_reader`], [`count_bytes`] or
+//! [`count_path`]:
+//!
+//! ```
+//! let opt = cw::Opt {
+//!     lines: true,
+//!     words: true,
+//!     ..cw::Opt::default()
+//! };
+//!
+//! let counts = cw::count_reader(&b"one two\nthree\n"[..], &opt).unwrap();
+//! assert_eq!(counts.lines, 2);
+//! assert_eq!(counts.words, 3);
+//! ```
+//!
+//! Unlike the binary, no metrics are enabled by default: an empty `Opt` only
+//! counts bytes.
+
+use std::convert::TryFrom;
+use std::io::{self, Read};
+use std::path::Path;
+
 pub mod args;
+pub mod baseline;
+pub mod cache;
 pub mod count;
+mod decode;
+mod decompress;
+pub mod diff;
+mod filter;
+pub mod frequency;
+pub mod output;
 pub mod siginfo;
+mod unique;
+
+pub use crate::args::Opt;
+pub use crate::count::Counts;
+use crate::count::{Counter, Strategy};
+
+/// Count the metrics enabled in `opt` from a reader.
+///
+/// Fails with `InvalidInput` if no strategy can count every requested metric.
+pub fn count_reader<R: Read>(r: R, opt: &Opt) -> io::Result<Counts> {
+    let mut count = Counts::default();
+    Strategy::try_from(opt)?.count(r, &mut count, opt)?;
+    Ok(count)
+}
+
+/// Count the metrics enabled in `opt` from input already in memory.
+///
+/// Reading never fails, so errors only come from `opt`: `InvalidInput` if no
+/// strategy can count every requested metric, as with `count_reader`, or
+/// `InvalidData` for corrupt input with `decompress`.
+///
+/// ```
+/// let opt = cw::Opt::builder().lines(true).words(true).build();
+///
+/// let counts = cw::count_bytes(b"one two\nthree\n", &opt).unwrap();
+/// assert_eq!((counts.lines, counts.words, counts.bytes), (2, 3, 14));
+/// ```
+pub fn count_bytes(data: &[u8], opt: &Opt) -> io::Result<Counts> {
+    let mut count = Counts::default();
+    Strategy::try_from(opt)?.count_slice(data, &mut count, opt)?;
+    Ok(count)
+}
+
+/// Count the metrics enabled in `opt` from the file at `path`.
+///
+/// The returned `Counts` has its `path` set.
+pub fn count_path<P: AsRef<Path>>(path: P, opt: &Opt) -> io::Result<Counts> {
+    Strategy::try_from(opt)?.count_file(path, opt)
+}
+
+#[test]
+fn test_count_bytes() {
+    let input = "foo\nbar\tbaz\n\u{f3}ne  two\r\n\nhmm".as_bytes();
+
+    for opt in &[
+        Opt::default(),
+        Opt::builder().lines(true).build(),
+        Opt::builder()
+            .lines(true)
+            .words(true)
+            .longest_line(true)
+            .build(),
+        Opt::builder().chars(true).words(true).build(),
+        Opt::builder().lines(true).count_partial_line(true).build(),
+        Opt::builder().graphemes(true).blank_lines(true).build(),
+    ] {
+        let a = count_reader(input, opt).unwrap();
+        let b = count_bytes(input, opt).unwrap();
+        assert_eq!(
+            (a.lines, a.words, a.chars, a.bytes, a.longest_line),
+            (b.lines, b.words, b.chars, b.bytes, b.longest_line),
+            "{:?}",
+            opt
+        );
+        assert_eq!((a.graphemes, a.blank_lines), (b.graphemes, b.blank_lines));
+    }
+
+    // Documented as countable together on Opt::builder, and not
+    let supported = Opt::builder()
+        .words(true)
+        .chars(true)
+        .graphemes(true)
+        .build();
+    assert!(count_bytes(input, &supported).is_ok());
+
+    let unsupported = Opt::builder()
+        .chars(true)
+        .words(true)
+        .word_separators(Some(args::ByteSet::ascii_whitespace()))
+        .build();
+    assert_eq!(
+        count_bytes(input, &unsupported).unwrap_err().kind(),
+        io::ErrorKind::InvalidInput
+    );
+}
+
+#[test]
+fn test_builder_defaults() {
+    use structopt::StructOpt;
+
+    let built = Opt::builder().longest_line(true).build();
+    let cli = Opt {
+        longest_line: true,
+        ..Opt::from_iter(["cw"])
+    };
+    assert_eq!(built.tab_width, 8);
+    assert_eq!(format!("{:?}", built), format!("{:?}", cli));
+
+    // So a tab measures the same as with `cw -L`
+    let count = count_bytes(b"\tx\n", &built).unwrap();
+    assert_eq!(count.longest_line, 9);
+}
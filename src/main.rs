@@ -1,14 +1,24 @@
-use std::collections::BinaryHeap;
+use std::collections::{BTreeMap, BinaryHeap, HashSet};
+use std::convert::TryFrom;
+use std::ffi::OsString;
+use std::fmt;
 use std::fs::File;
-use std::io::{self, BufRead, BufReader, Read};
+use std::io::{self, BufRead, BufReader, IsTerminal, Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
 use std::sync::atomic::AtomicUsize;
+use std::time::{Duration, Instant};
 use structopt::StructOpt;
 
 use crossbeam_utils::thread;
+use walkdir::WalkDir;
 
-use cw::args::Opt;
-use cw::count::{Counter, Counts, Strategy};
+use cw::args::{ColorChoice, Opt, Total};
+use cw::baseline::Baseline;
+use cw::cache::Cache;
+use cw::count::{human_bytes, BinaryFile, Counter, Counts, Strategy};
+use cw::diff::Diff;
+use cw::frequency::Frequency;
+use cw::output::{print_error, Printer};
 use cw::siginfo;
 
 struct ComputedCount(usize, Result<Counts, (PathBuf, io::Error)>);
@@ -21,9 +31,10 @@ impl PartialEq for ComputedCount {
 impl Eq for ComputedCount {}
 impl PartialOrd for ComputedCount {
     fn partial_cmp(&self, o: &Self) -> Option<std::cmp::Ordering> {
-        o.0.partial_cmp(&self.0)
+        Some(self.cmp(o))
     }
 }
+// Reversed, so the BinaryHeap pops the lowest input index first
 impl Ord for ComputedCount {
     fn cmp(&self, o: &Self) -> std::cmp::Ordering {
         o.0.cmp(&self.0)
@@ -41,7 +52,7 @@ fn bytes_to_pathbuf(bytes: &[u8]) -> PathBuf {
 #[cfg(not(unix))]
 fn bytes_to_pathbuf(bytes: &[u8]) -> PathBuf {
     // Blargh, it'll do for now, I guess :/
-    PathBuf::from(String::from_utf8_lossy(&bytes).to_string())
+    PathBuf::from(String::from_utf8_lossy(bytes).to_string())
 }
 
 fn append_delimited_filenames_read<R: Read>(
@@ -63,33 +74,646 @@ fn append_delimited_filenames_read<R: Read>(
 
 fn append_delimited_filenames<P: AsRef<Path>>(
     source: P,
-    mut dest: &mut Vec<PathBuf>,
+    dest: &mut Vec<PathBuf>,
     delimiter: u8,
 ) -> io::Result<()> {
     let source = source.as_ref();
 
     if source == Path::new("-") {
-        append_delimited_filenames_read(&mut io::stdin(), &mut dest, delimiter)
+        append_delimited_filenames_read(&mut io::stdin(), dest, delimiter)
     } else {
-        append_delimited_filenames_read(File::open(source)?, &mut dest, delimiter)
+        append_delimited_filenames_read(File::open(source)?, dest, delimiter)
+    }
+}
+
+// Remove repeated paths, keeping the first of each, comparing canonical paths
+// where possible
+fn dedup_paths(paths: &mut Vec<PathBuf>) {
+    let mut seen = HashSet::with_capacity(paths.len());
+
+    paths.retain(|path| seen.insert(path.canonicalize().unwrap_or_else(|_| path.clone())));
+}
+
+// Expand any glob patterns in the input list in place, returning false if a
+// pattern was invalid or matched nothing
+fn expand_globs(opt: &mut Opt) -> bool {
+    let mut ok = true;
+    let mut expanded = Vec::with_capacity(opt.input.len());
+
+    for path in std::mem::take(&mut opt.input) {
+        let pattern = match path.to_str() {
+            Some(pattern) if pattern.contains(&['*', '?', '['][..]) => pattern,
+            _ => {
+                expanded.push(path);
+                continue;
+            }
+        };
+
+        let paths = match glob::glob(pattern) {
+            Ok(paths) => paths,
+            Err(e) => {
+                ok = false;
+                report_error(opt, Path::new(pattern), e);
+                continue;
+            }
+        };
+
+        let start = expanded.len();
+        for entry in paths {
+            match entry {
+                Ok(path) => expanded.push(path),
+                Err(e) => {
+                    ok = false;
+                    report_error(opt, e.path(), e.error());
+                }
+            }
+        }
+
+        if expanded.len() == start {
+            ok = false;
+            report_error(opt, Path::new(pattern), "no matches found");
+        }
+    }
+
+    opt.input = expanded;
+    ok
+}
+
+// Whether a path matches any --exclude pattern
+fn is_excluded(path: &Path, opt: &Opt) -> bool {
+    opt.exclude.iter().any(|pattern| {
+        if pattern.as_str().contains('/') {
+            pattern.matches_path(path)
+        } else {
+            path.file_name()
+                .is_some_and(|name| pattern.matches(&name.to_string_lossy()))
+        }
+    })
+}
+
+// Remove inputs matching any --exclude pattern
+fn exclude_paths(opt: &mut Opt) {
+    let mut input = std::mem::take(&mut opt.input);
+    input.retain(|path| !is_excluded(path, opt));
+    opt.input = input;
+}
+
+// Replace directories in the input list with the regular files beneath them,
+// returning false if any part of the walk failed.  With --du directories are
+// left to be counted whole.
+fn expand_directories(opt: &mut Opt) -> bool {
+    if opt.du {
+        return true;
+    }
+
+    let mut ok = true;
+    let mut expanded = Vec::with_capacity(opt.input.len());
+
+    for path in std::mem::take(&mut opt.input) {
+        if !path.is_dir() {
+            expanded.push(path);
+            continue;
+        }
+
+        walk_directory(&path, opt, &mut expanded, &mut |path, e| {
+            ok = false;
+            report_error(opt, path, e);
+        });
+    }
+
+    opt.input = expanded;
+    ok
+}
+
+// Find the regular files beneath a directory in name order, passing any errors
+// along the way to `error`
+fn walk_directory(
+    path: &Path,
+    opt: &Opt,
+    files: &mut Vec<PathBuf>,
+    error: &mut dyn FnMut(&Path, &dyn fmt::Display),
+) {
+    if opt.gitignore {
+        return walk_ignoring(path, opt, files, error);
+    }
+
+    let walker = WalkDir::new(path)
+        .follow_links(opt.follow_symlinks)
+        .sort_by_file_name();
+
+    for entry in walker {
+        match entry {
+            Ok(entry) if entry.file_type().is_file() => files.push(entry.into_path()),
+            Ok(_) => (),
+            Err(e) => {
+                let path = e.path().unwrap_or(path).to_path_buf();
+                match e.into_io_error() {
+                    Some(e) => error(&path, &e),
+                    None => error(&path, &"filesystem loop detected"),
+                }
+            }
+        }
+    }
+}
+
+// Walk a directory as ripgrep would, skipping files ignored by .gitignore and
+// friends, and hidden files unless --hidden is given
+fn walk_ignoring(
+    path: &Path,
+    opt: &Opt,
+    files: &mut Vec<PathBuf>,
+    error: &mut dyn FnMut(&Path, &dyn fmt::Display),
+) {
+    let walker = ignore::WalkBuilder::new(path)
+        .hidden(!opt.hidden)
+        .follow_links(opt.follow_symlinks)
+        .filter_entry(|entry| entry.file_name() != ".git")
+        .sort_by_file_name(|a, b| a.cmp(b))
+        .build();
+
+    for entry in walker {
+        match entry {
+            Ok(entry) if entry.file_type().is_some_and(|t| t.is_file()) => {
+                files.push(entry.into_path())
+            }
+            Ok(_) => (),
+            Err(e) => error(path, &e),
+        }
+    }
+}
+
+// Sum the counts of the regular files beneath a directory for --du, failing
+// at the first that can't be found or counted.  Skipped binary files are left
+// out quietly.
+fn count_directory(
+    strategy: Strategy,
+    path: &Path,
+    opt: &Opt,
+    cache: Option<&Cache>,
+) -> io::Result<Counts> {
+    let mut files = vec![];
+    let mut failure = None;
+    walk_directory(path, opt, &mut files, &mut |path, e| {
+        failure.get_or_insert_with(|| io::Error::other(format!("{}: {}", path.display(), e)));
+    });
+
+    if let Some(e) = failure {
+        return Err(e);
+    }
+
+    let mut count = Counts::new(path);
+    for file in files.iter().filter(|file| !is_excluded(file, opt)) {
+        let ret = match cache {
+            Some(cache) => count_cached(strategy, file, opt, cache),
+            None => strategy.count_file(file, opt),
+        };
+
+        match ret {
+            Ok(file_count) => count.add(&file_count),
+            Err(e) if BinaryFile::is(&e) => (),
+            Err(e) => {
+                return Err(io::Error::new(
+                    e.kind(),
+                    format!("{}: {}", file.display(), e),
+                ))
+            }
+        }
+    }
+
+    Ok(count)
+}
+
+/// A `processed N/M files` line on stderr, redrawn in place
+struct Progress {
+    enabled: bool,
+    done: usize,
+    items: usize,
+    last: Option<Instant>,
+    width: usize,
+}
+
+impl Progress {
+    const INTERVAL: Duration = Duration::from_millis(100);
+
+    fn new(opt: &Opt) -> Self {
+        Self {
+            enabled: opt.progress && io::stderr().is_terminal(),
+            done: 0,
+            items: opt.input.len(),
+            last: None,
+            width: 0,
+        }
+    }
+
+    fn inc(&mut self) {
+        self.done += 1;
+
+        if !self.enabled || self.last.is_some_and(|t| t.elapsed() < Self::INTERVAL) {
+            return;
+        }
+
+        let line = format!("processed {}/{} files", self.done, self.items);
+        self.width = line.len();
+        let _ = write!(io::stderr(), "\r{}", line);
+        self.last = Some(Instant::now());
+    }
+
+    fn finish(&mut self) {
+        if self.enabled && self.width > 0 {
+            let _ = write!(io::stderr(), "\r{:1$}\r", "", self.width);
+            self.width = 0;
+        }
+    }
+}
+
+fn count_file_timed(
+    strategy: Strategy,
+    path: &Path,
+    opt: &Opt,
+    cache: Option<&Cache>,
+) -> io::Result<Counts> {
+    // Failures are left for the real count to report
+    for _ in 0..opt.warmup.unwrap_or(0) {
+        if let Ok(mut file) = File::open(path) {
+            let _ = io::copy(&mut file, &mut io::sink());
+        }
+    }
+
+    let start = Instant::now();
+    let ret = if is_stdin(path) {
+        // Inputs are already spread across threads, so stdin gets just one
+        let mut count = Counts {
+            path: Some(opt.stdin_name.as_deref().unwrap_or(path).to_path_buf()),
+            ..Counts::default()
+        };
+        count_stdin(strategy, &mut count, opt, 1).map(|()| count)
+    } else if opt.du && path.is_dir() {
+        count_directory(strategy, path, opt, cache)
+    } else if let Some(cache) = cache {
+        count_cached(strategy, path, opt, cache)
+    } else {
+        strategy.count_file(path, opt)
+    };
+
+    if opt.timings {
+        eprintln!("{}: {:.1?}", path.display(), start.elapsed());
+    }
+
+    if let Ok(ref count) = ret {
+        warn_partial_record(count, opt);
+    }
+
+    ret
+}
+
+// Whether an input path means standard input
+fn is_stdin(path: &Path) -> bool {
+    path == Path::new("-")
+}
+
+// Report a problem with an input on stderr, as JSON with --json-errors
+fn report_error<E: fmt::Display>(opt: &Opt, path: &Path, error: E) {
+    let _ = print_error(opt, path, &error, io::stderr().lock());
+}
+
+// Count a file, or reuse its counts from the --cache if it hasn't changed
+fn count_cached(strategy: Strategy, path: &Path, opt: &Opt, cache: &Cache) -> io::Result<Counts> {
+    if let Some(count) = cache.get(path) {
+        return Ok(count);
+    }
+
+    // Taken first, so a file changed while it's counted is counted again next time
+    let meta = std::fs::metadata(path).ok();
+    let count = strategy.count_file(path, opt)?;
+
+    if let Some(meta) = meta.filter(|meta| meta.is_file()) {
+        cache.insert(path, &meta, &count);
+    }
+    Ok(count)
+}
+
+// Count standard input into `count`, splitting it between `threads` if the
+// strategy allows.  After Ctrl-C, `count` holds what was counted before it.
+fn count_stdin(
+    strategy: Strategy,
+    count: &mut Counts,
+    opt: &Opt,
+    threads: usize,
+) -> io::Result<()> {
+    strategy.count_parallel(io::stdin().lock(), count, opt, threads)
+}
+
+// Count standard input into `count` for --tee, passing it all through to stdout
+fn count_tee(strategy: Strategy, count: &mut Counts, opt: &Opt) -> io::Result<()> {
+    strategy.count_tee(io::stdin().lock(), io::stdout().lock(), count, opt)
+}
+
+// Print the rows counted before Ctrl-C, including any held back by the
+// printer for alignment or sorting
+fn print_interrupted<W: Write>(
+    out: &mut Printer<'_, W>,
+    extensions: &BTreeMap<OsString, Counts>,
+    opt: &Opt,
+) {
+    if opt.total != Total::Only {
+        for count in extensions.values() {
+            let _ = out.print(count);
+        }
+    }
+    let _ = out.finish_interrupted();
+}
+
+// After the first Ctrl-C, print what was counted before it to stderr and exit.
+// A second Ctrl-C quits at once without waiting for this.
+fn exit_interrupted(total: &Counts, opt: &Opt) -> ! {
+    eprintln!("cw: interrupted");
+    let _ = total.print(opt, io::stderr().lock());
+    std::process::exit(siginfo::INTERRUPTED_EXIT_CODE);
+}
+
+// How often the main thread checks for Ctrl-C while waiting on workers
+const INTERRUPT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// How often --follow checks for more input
+const FOLLOW_INTERVAL: Duration = Duration::from_secs(1);
+
+// Count a file as it grows for --follow, printing its counts each time more
+// whole lines have been counted.  Lines are counted once they're complete, so
+// none are split between counts, and the counts start again if the file is
+// truncated.  Only returns if reading fails.
+fn follow(strategy: Strategy, path: &Path, opt: &Opt) -> io::Result<()> {
+    let delimiter = opt.line_delimiter.unwrap_or(b'\n');
+    let mut file = File::open(path)?;
+    let mut count = Counts::new(path);
+    let mut offset = 0;
+    let mut pending = vec![];
+    let mut buf = vec![0; 64 * 1024];
+    let mut changed = true;
+
+    loop {
+        let len = file.read(&mut buf)?;
+        if len == 0 {
+            if changed {
+                let mut out = io::stdout().lock();
+                count.print(opt, &mut out)?;
+                out.flush()?;
+                changed = false;
+            }
+
+            std::thread::sleep(FOLLOW_INTERVAL);
+
+            if file.metadata()?.len() < offset {
+                eprintln!("cw: {}: file truncated", path.display());
+                file.seek(SeekFrom::Start(0))?;
+                count.reset();
+                offset = 0;
+                pending.clear();
+                changed = true;
+            }
+            continue;
+        }
+
+        offset += len as u64;
+        pending.extend_from_slice(&buf[..len]);
+
+        if let Some(end) = memchr::memrchr(delimiter, &pending) {
+            let mut lines = Counts::default();
+            strategy.count_slice(&pending[..=end], &mut lines, opt)?;
+
+            // Line numbers continue on from the lines already counted
+            if lines.longest_line > count.longest_line {
+                lines.longest_line_at += count.lines;
+            }
+            count.add(&lines);
+
+            pending.drain(..=end);
+            changed = true;
+        }
+    }
+}
+
+// Add counts to the group for their file's extension, for --by-extension
+fn add_by_extension(groups: &mut BTreeMap<OsString, Counts>, count: &Counts) {
+    let extension = count
+        .path
+        .as_deref()
+        .and_then(Path::extension)
+        .map_or_else(|| OsString::from("(none)"), OsString::from);
+
+    groups
+        .entry(extension.clone())
+        .or_insert_with(|| Counts::new(extension))
+        .add(count);
+}
+
+// Print the total with the number of inputs counted as a single JSON object,
+// for --summary-json
+fn print_summary(total: &Counts, files: usize, opt: &Opt) -> io::Result<()> {
+    let mut out = io::stdout().lock();
+    total.print_json_with(opt, &[("files", files as u64)], &mut out)?;
+    writeln!(&mut out)
+}
+
+// Report each --limit the count is over, returning whether there were any
+fn check_limits(count: &Counts, opt: &Opt) -> bool {
+    let mut exceeded = false;
+
+    for limit in &opt.limit {
+        let value = count.metric(limit.metric).unwrap_or(0);
+        if value > limit.max {
+            let name = count.path.as_deref().unwrap_or_else(|| Path::new("-"));
+            eprintln!(
+                "{}: {} {} is over the limit of {}",
+                name.display(),
+                value,
+                limit.metric,
+                limit.max
+            );
+            exceeded = true;
+        }
+    }
+
+    exceeded
+}
+
+// Note any bytes beyond the last whole --record-bytes record
+fn warn_partial_record(count: &Counts, opt: &Opt) {
+    if let Some(size) = opt.record_bytes {
+        let leftover = count.bytes % size.get();
+        if leftover > 0 {
+            let name = count.path.as_deref().unwrap_or_else(|| Path::new("-"));
+            eprintln!(
+                "{}: {} bytes left over after the last whole record",
+                name.display(),
+                leftover
+            );
+        }
+    }
+}
+
+// Whether to write ANSI colors to a stream, which is every color decision we
+// make.  An explicit --color wins, then a non-empty NO_COLOR disables color and
+// CLICOLOR_FORCE other than 0 enables it, and otherwise it's used on terminals.
+fn use_color(choice: ColorChoice, terminal: bool) -> bool {
+    let env_set = |name| std::env::var_os(name).is_some_and(|v| !v.is_empty());
+
+    match choice {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto if env_set("NO_COLOR") => false,
+        ColorChoice::Auto if env_set("CLICOLOR_FORCE") => {
+            std::env::var_os("CLICOLOR_FORCE").is_some_and(|v| v != "0")
+        }
+        ColorChoice::Auto => terminal,
+    }
+}
+
+// Print the version along with the SIMD support compiled in, for --version-verbose
+fn print_version_verbose() {
+    let backend = if cfg!(feature = "runtime-dispatch-simd") {
+        "runtime-dispatch-simd"
+    } else if cfg!(feature = "generic-simd") {
+        "generic-simd"
+    } else {
+        "scalar"
+    };
+
+    let mut target_features = vec![];
+    if cfg!(target_feature = "sse2") {
+        target_features.push("sse2");
+    }
+    if cfg!(target_feature = "avx2") {
+        target_features.push("avx2");
+    }
+    if cfg!(target_feature = "neon") {
+        target_features.push("neon");
+    }
+
+    println!("cw {}", env!("CARGO_PKG_VERSION"));
+    println!("bytecount backend: {}", backend);
+    println!(
+        "runtime SIMD dispatch: {}",
+        if cfg!(feature = "runtime-dispatch-simd") {
+            "enabled"
+        } else {
+            "disabled"
+        }
+    );
+    if target_features.is_empty() {
+        println!("target features: none");
+    } else {
+        println!("target features: {}", target_features.join(", "));
     }
 }
 
 fn main() -> io::Result<()> {
     let mut opt = Opt::from_args();
     let mut total = Counts::new("total");
-    let stdout = io::stdout();
-    let mut out = stdout.lock();
     let mut exit_code = 0;
 
-    siginfo::hook_signal();
+    if opt.version_verbose {
+        print_version_verbose();
+        return Ok(());
+    }
+
+    if opt.self_test {
+        let ok = cw::count::self_test(io::stdout().lock())?;
+        std::process::exit(if ok { 0 } else { 1 });
+    }
+
+    siginfo::hook_signal(opt.siginfo_signal);
 
-    if !(opt.bytes || opt.words || opt.chars || opt.lines || opt.longest_line) {
+    opt.color = if use_color(opt.color, io::stdout().is_terminal()) {
+        ColorChoice::Always
+    } else {
+        ColorChoice::Never
+    };
+
+    if !(opt.bytes
+        || opt.words
+        || opt.chars
+        || opt.lines
+        || opt.longest_line
+        || opt.longest_line_number
+        || opt.shortest_line
+        || opt.avg_line_length
+        || opt.longest_word
+        || opt.max_words_per_line
+        || opt.unique
+        || opt.unique_global
+        || opt.paragraphs
+        || opt.record_bytes.is_some()
+        || !opt.count_byte.is_empty()
+        || opt.graphemes
+        || opt.blank_lines)
+    {
         opt.lines = true;
         opt.bytes = true;
         opt.words = true;
     }
 
+    if opt.longest_line_number {
+        opt.longest_line = true;
+    }
+
+    if opt.unique_global {
+        opt.unique = true;
+    }
+
+    if opt.no_total {
+        opt.total = Total::Never;
+    }
+
+    if opt.posix_lines {
+        opt.count_partial_line = false;
+    }
+
+    if opt.summary_json {
+        opt.total = Total::Only;
+    }
+
+    // Like GNU wc, a lone total goes unlabelled
+    if opt.total == Total::Only {
+        total.path = None;
+    }
+
+    if opt.decompress && !cfg!(feature = "decompress") {
+        eprintln!("cw: --decompress requires cw to be built with the decompress feature");
+        std::process::exit(1);
+    }
+
+    if opt.top.is_some() && opt.sort.is_none() && !opt.frequency {
+        eprintln!("cw: --top requires --sort or --frequency");
+        std::process::exit(1);
+    }
+
+    let enabled = Counts::labels(&opt);
+    for limit in &opt.limit {
+        if !enabled.contains(&limit.metric) {
+            eprintln!(
+                "cw: --limit uses {}, which is not being counted",
+                limit.metric
+            );
+            std::process::exit(1);
+        }
+    }
+
+    if opt.explain {
+        Strategy::explain(&opt, io::stdout().lock())?;
+        return Ok(());
+    }
+
+    // Only count stdin if we weren't given anything else to count, even if
+    // that turns out to be an empty list of files
+    let read_stdin = opt.input.is_empty()
+        && opt.files_from.is_none()
+        && opt.files0_from.is_none()
+        && opt.files_list.is_none();
+
+    if opt.glob && !expand_globs(&mut opt) {
+        exit_code = 1;
+    }
+
     if let Some(ref path) = opt.files_from {
         append_delimited_filenames(path, &mut opt.input, b'\n')?;
     }
@@ -98,16 +722,221 @@ fn main() -> io::Result<()> {
         append_delimited_filenames(path, &mut opt.input, b'\0')?;
     }
 
-    let strategy = Strategy::from(&opt);
+    if let Some(ref path) = opt.files_list {
+        let delimiter = opt.files_delimiter.unwrap_or(b'\n');
+        append_delimited_filenames(path, &mut opt.input, delimiter)?;
+    }
+
+    if opt.recursive && !expand_directories(&mut opt) {
+        exit_code = 1;
+    }
+
+    if !opt.exclude.is_empty() {
+        exclude_paths(&mut opt);
+    }
+
+    if opt.unique_paths {
+        dedup_paths(&mut opt.input);
+    }
+
+    // Standard input can only be read once, whether for a list of files or to count
+    let list_from_stdin = [&opt.files_from, &opt.files0_from, &opt.files_list]
+        .iter()
+        .any(|list| list.as_deref().is_some_and(is_stdin));
+    let stdin_inputs = opt.input.iter().filter(|path| is_stdin(path)).count();
+    if stdin_inputs > 1 || (stdin_inputs > 0 && list_from_stdin) {
+        eprintln!("cw: standard input (-) can only be read once");
+        std::process::exit(1);
+    }
+
+    if opt.aggregate {
+        opt.total = Total::Only;
+        total.path = match opt.aggregate_name {
+            Some(ref name) => Some(PathBuf::from(name)),
+            None => opt.input.first().cloned(),
+        };
+    }
+
+    if opt.frequency {
+        let mut frequency = Frequency::new(&opt);
+
+        if read_stdin {
+            frequency.count(io::stdin())?;
+        }
+
+        for path in &opt.input {
+            let ret = if is_stdin(path) {
+                frequency.count(io::stdin())
+            } else {
+                frequency.count_file(path)
+            };
+
+            if let Err(e) = ret {
+                exit_code = 1;
+                report_error(&opt, path, e);
+            }
+        }
+
+        if !opt.quiet {
+            frequency.print(opt.top.unwrap_or(20), io::stdout().lock())?;
+        }
+        std::process::exit(exit_code);
+    }
+
+    if opt.diff {
+        let mut diff = Diff::default();
+
+        if read_stdin {
+            diff.count(io::stdin())?;
+        }
+
+        for path in &opt.input {
+            let ret = if is_stdin(path) {
+                diff.count(io::stdin())
+            } else {
+                diff.count_file(path)
+            };
+
+            if let Err(e) = ret {
+                exit_code = 1;
+                report_error(&opt, path, e);
+            }
+        }
+
+        if !opt.quiet {
+            diff.print(&opt, io::stdout().lock())?;
+        }
+        std::process::exit(exit_code);
+    }
+
+    let strategy = match (Strategy::try_from(&opt), opt.strategy) {
+        (Ok(strategy), _) => strategy,
+        (Err(_), Some(forced)) => {
+            eprintln!(
+                "cw: strategy {:?} can't count {}",
+                forced,
+                forced.lacks(&opt).join(", ")
+            );
+            std::process::exit(1);
+        }
+        (Err(e), None) => {
+            eprintln!("cw: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    if let Some(ref template) = opt.format {
+        if let Err(e) = template.validate(&opt, &strategy.capabilities()) {
+            eprintln!("cw: {}", e);
+            std::process::exit(1);
+        }
+    }
+    if opt.follow {
+        let path = match opt.input.as_slice() {
+            [path] if !is_stdin(path) && opt.skip_lines == 0 => path,
+            _ => {
+                eprintln!("cw: --follow needs a single file, without --skip-lines");
+                std::process::exit(1);
+            }
+        };
+
+        if let Err(e) = follow(strategy, path, &opt) {
+            report_error(&opt, path, e);
+        }
+        std::process::exit(1);
+    }
+
+    // Stdout is locked per write rather than for the whole run, so it's never
+    // held while waiting on input.  With --tee it's for the input instead.
+    let target: Box<dyn Write> = if opt.tee {
+        Box::new(io::stderr())
+    } else {
+        Box::new(io::stdout())
+    };
+    let mut out = Printer::new(&opt, target);
+
+    if let Some(ref path) = opt.baseline {
+        match Baseline::load(path) {
+            Ok(baseline) => out = out.with_baseline(baseline),
+            Err(e) => {
+                eprintln!("cw: {}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let threads = if opt.threads == 0 {
+        match std::thread::available_parallelism() {
+            Ok(n) => n.get(),
+            Err(e) => {
+                eprintln!("cw: unable to determine the number of CPUs: {}", e);
+                std::process::exit(1);
+            }
+        }
+    } else {
+        opt.threads
+    };
+
+    siginfo::hook_interrupt();
+
+    if read_stdin {
+        let mut count = Counts {
+            path: opt.stdin_name.clone(),
+            ..Counts::default()
+        };
+        let ret = if opt.tee {
+            count_tee(strategy, &mut count, &opt)
+        } else {
+            count_stdin(strategy, &mut count, &opt, threads)
+        };
+        match ret {
+            Err(_) if siginfo::interrupted() => exit_interrupted(&count, &opt),
+            ret => ret?,
+        }
+        warn_partial_record(&count, &opt);
+        let exceeded = check_limits(&count, &opt);
+
+        if opt.total != Total::Only {
+            out.print(&count)?;
+        }
 
-    if opt.input.is_empty() {
-        let mut count = Counts::default();
-        strategy.count(&mut io::stdin(), &mut count, &opt)?;
-        return count.print(&opt, &mut out);
+        if opt.total == Total::Always || opt.total == Total::Only {
+            total.add(&count);
+            if opt.summary_json {
+                print_summary(&total, 1, &opt)?;
+            } else {
+                out.print_total(&total)?;
+            }
+        }
+
+        out.finish()?;
+        if opt.count_files {
+            eprintln!("cw: counted 1 of 1 files");
+        }
+        if exceeded {
+            std::process::exit(1);
+        }
+        return Ok(());
     }
 
+    let cache = match opt.cache {
+        Some(ref path) => match Cache::load(path, &opt) {
+            Ok(cache) => Some(cache),
+            Err(e) => {
+                eprintln!("cw: {}: {}, ignoring", path.display(), e);
+                Some(Cache::new(&opt))
+            }
+        },
+        None => None,
+    };
+
     let items = opt.input.len();
-    let threads = std::cmp::min(items, opt.threads);
+    let threads = std::cmp::min(items, threads);
+    let start = Instant::now();
+    let mut progress = Progress::new(&opt);
+    let mut failed = 0;
+    let mut skipped = 0;
+    let mut extensions = BTreeMap::new();
 
     if threads > 1 {
         let count_idx = AtomicUsize::new(0);
@@ -116,22 +945,37 @@ fn main() -> io::Result<()> {
 
             // Create refs, so we only move these refs into scope.spawn
             let count_idx = &count_idx;
+            let cache = cache.as_ref();
             let opt = &opt;
 
+            // Each input being counted or waiting to be printed holds a permit,
+            // bounding how far workers can run ahead of a slow file
+            let window = std::cmp::max(opt.reorder_window, 1);
+            let (permit_tx, permit_rx) = crossbeam_channel::bounded(window);
+            for _ in 0..window {
+                permit_tx.send(()).expect("permit");
+            }
+
             for _ in 0..threads {
                 let result_tx = result_tx.clone();
+                let permit_tx = permit_tx.clone();
+                let permit_rx = permit_rx.clone();
 
                 scope.spawn(move |_| {
                     let mut i;
                     loop {
+                        if permit_rx.recv().is_err() {
+                            break;
+                        }
+
                         i = count_idx.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
                         if i >= items {
+                            let _ = permit_tx.send(());
                             break;
                         }
                         let path = &opt.input[i];
 
-                        let ret = strategy
-                            .count_file(&path, &opt)
+                        let ret = count_file_timed(strategy, path, opt, cache)
                             .map_err(|e| (path.clone(), e));
 
                         if result_tx.send(ComputedCount(i, ret)).is_err() {
@@ -147,21 +991,64 @@ fn main() -> io::Result<()> {
             let mut buffered = BinaryHeap::new();
             let mut next = 0;
 
-            for item in result_rx {
-                buffered.push(item);
+            loop {
+                // Ctrl-C may not reach a worker blocked reading, so check for
+                // it while waiting rather than relying on their results
+                let item = match result_rx.recv_timeout(INTERRUPT_POLL_INTERVAL) {
+                    Ok(item) => Some(item),
+                    Err(crossbeam_channel::RecvTimeoutError::Timeout) => None,
+                    Err(crossbeam_channel::RecvTimeoutError::Disconnected) => break,
+                };
+
+                // Workers stop part way through their files, so only those
+                // they finished count, in order but skipping the rest
+                if siginfo::interrupted() {
+                    buffered.extend(item);
+                    while let Some(ComputedCount(_, count)) = buffered.pop() {
+                        if let Ok(count) = count {
+                            total.add(&count);
+                            if opt.by_extension {
+                                add_by_extension(&mut extensions, &count);
+                            } else if opt.total != Total::Only {
+                                let _ = out.print(&count);
+                            }
+                        }
+                    }
+                    progress.finish();
+                    print_interrupted(&mut out, &extensions, opt);
+                    exit_interrupted(&total, opt);
+                }
+                match item {
+                    Some(item) => buffered.push(item),
+                    None => continue,
+                }
 
                 while buffered.peek().map(|x| x.0) == Some(next) {
                     let ComputedCount(_, count) = buffered.pop().expect("binary heap pop");
+                    let _ = permit_tx.send(());
                     next += 1;
+                    progress.inc();
 
                     match count {
                         Ok(count) => {
+                            if check_limits(&count, opt) {
+                                exit_code = 1;
+                            }
                             total.add(&count);
-                            count.print(&opt, &mut out).expect("stdout");
+                            if opt.by_extension {
+                                add_by_extension(&mut extensions, &count);
+                            } else if opt.total != Total::Only {
+                                out.print(&count).expect("stdout");
+                            }
+                        }
+                        Err((path, e)) if BinaryFile::is(&e) => {
+                            skipped += 1;
+                            report_error(opt, &path, e);
                         }
                         Err((path, e)) => {
                             exit_code = 1;
-                            eprintln!("{}: {}", path.display(), e);
+                            failed += 1;
+                            report_error(opt, &path, e);
                         }
                     }
                 }
@@ -170,21 +1057,94 @@ fn main() -> io::Result<()> {
         .expect("thread");
     } else {
         for path in &opt.input {
-            match strategy.count_file(&path, &opt) {
+            let ret = count_file_timed(strategy, path, &opt, cache.as_ref());
+            if siginfo::interrupted() {
+                // The file may have been finished before Ctrl-C arrived
+                if let Ok(ref count) = ret {
+                    total.add(count);
+                    if opt.by_extension {
+                        add_by_extension(&mut extensions, count);
+                    } else if opt.total != Total::Only {
+                        out.print(count)?;
+                    }
+                }
+                progress.finish();
+                print_interrupted(&mut out, &extensions, &opt);
+                exit_interrupted(&total, &opt);
+            }
+            progress.inc();
+
+            match ret {
                 Ok(count) => {
+                    if check_limits(&count, &opt) {
+                        exit_code = 1;
+                    }
                     total.add(&count);
-                    count.print(&opt, &mut out)?;
+                    if opt.by_extension {
+                        add_by_extension(&mut extensions, &count);
+                    } else if opt.total != Total::Only {
+                        out.print(&count)?;
+                    }
+                }
+                Err(e) if BinaryFile::is(&e) => {
+                    skipped += 1;
+                    report_error(&opt, path, e);
                 }
                 Err(e) => {
                     exit_code = 1;
-                    eprintln!("{}: {}", path.display(), e);
+                    failed += 1;
+                    report_error(&opt, path, e);
                 }
             };
         }
     }
 
-    if opt.input.len() > 1 {
-        total.print(&opt, &mut out)?;
+    progress.finish();
+
+    if opt.total != Total::Only {
+        for count in extensions.values() {
+            out.print(count)?;
+        }
+    }
+
+    if opt.timings {
+        let elapsed = start.elapsed();
+        let rate = total.bytes as f64 / elapsed.as_secs_f64().max(f64::EPSILON);
+        eprintln!("total: {:.1?}, {}B/s", elapsed, human_bytes(rate as u64));
+    }
+
+    let print_total = match opt.total {
+        Total::Auto => opt.input.len() > 1,
+        Total::Always | Total::Only => true,
+        Total::Never => false,
+    };
+
+    if opt.summary_json {
+        print_summary(&total, items - failed - skipped, &opt)?;
+    } else if print_total {
+        out.print_total(&total)?;
+    }
+
+    out.finish()?;
+
+    if opt.count_files {
+        eprintln!(
+            "cw: counted {} of {} files",
+            items - failed - skipped,
+            items
+        );
+    }
+
+    if let (Some(cache), Some(path)) = (&cache, &opt.cache) {
+        if let Err(e) = cache.save(path, &opt) {
+            exit_code = 1;
+            eprintln!("cw: {}: {}", path.display(), e);
+        }
+    }
+
+    // Each failure has already been reported as JSON, and this isn't one
+    if failed > 0 && items > 1 && !opt.json_errors {
+        eprintln!("cw: {} of {} files could not be read", failed, items);
     }
 
     std::process::exit(exit_code);
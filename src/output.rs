@@ -0,0 +1,589 @@
+use std::fmt;
+use std::io::{self, Write};
+use std::path::Path;
+
+use crate::args::{ColorChoice, Opt};
+use crate::baseline::Baseline;
+use crate::count::{end_record, write_json_str, write_path, Capability, Counts};
+
+/// The overall shape of the output, as selected by the `Opt` format flags.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Columns,
+    Json,
+    JsonLines,
+    Csv,
+    Tabs,
+    Template,
+}
+
+impl From<&Opt> for Format {
+    fn from(opt: &Opt) -> Self {
+        if opt.format.is_some() {
+            Format::Template
+        } else if opt.json {
+            Format::Json
+        } else if opt.json_lines {
+            Format::JsonLines
+        } else if opt.csv {
+            Format::Csv
+        } else if opt.tabs {
+            Format::Tabs
+        } else {
+            Format::Columns
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Piece {
+    Literal(String),
+    Metric(&'static str),
+    Path,
+}
+
+/// A user-supplied output template, as given to `--format`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    pieces: Vec<Piece>,
+}
+
+impl Template {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let mut pieces = vec![];
+        let mut literal = String::new();
+        let mut chars = s.chars();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => match chars.next() {
+                    Some('t') => literal.push('\t'),
+                    Some('n') => literal.push('\n'),
+                    Some('\\') => literal.push('\\'),
+                    Some(c) => {
+                        literal.push('\\');
+                        literal.push(c);
+                    }
+                    None => literal.push('\\'),
+                },
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => return Err(format!("unterminated placeholder {{{}", name)),
+                        }
+                    }
+
+                    let piece = if name == "path" {
+                        Piece::Path
+                    } else {
+                        Counts::default()
+                            .metrics(&Opt::default())
+                            .into_iter()
+                            .find(|(metric, _, _)| *metric == name)
+                            .map(|(metric, _, _)| Piece::Metric(metric))
+                            .ok_or_else(|| format!("unknown placeholder {{{}}}", name))?
+                    };
+
+                    if !literal.is_empty() {
+                        pieces.push(Piece::Literal(std::mem::take(&mut literal)));
+                    }
+                    pieces.push(piece);
+                }
+                c => literal.push(c),
+            }
+        }
+
+        if !literal.is_empty() {
+            pieces.push(Piece::Literal(literal));
+        }
+
+        Ok(Self { pieces })
+    }
+
+    /// Check that every metric the template refers to is being counted, either
+    /// because `opt` asks for it or because `capability`, that of the strategy
+    /// counting, includes it anyway.
+    pub fn validate(&self, opt: &Opt, capability: &Capability) -> Result<(), String> {
+        let metrics = Counts::default().metrics(opt);
+
+        for piece in &self.pieces {
+            let name = match piece {
+                Piece::Metric(name) => name,
+                _ => continue,
+            };
+
+            if !capability.counts(name)
+                && !metrics
+                    .iter()
+                    .any(|(metric, enabled, _)| metric == name && *enabled)
+            {
+                return Err(format!(
+                    "--format uses {{{}}}, which is not being counted",
+                    name
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn render<W: Write>(&self, opt: &Opt, count: &Counts, mut out: W) -> io::Result<()> {
+        for piece in &self.pieces {
+            match piece {
+                Piece::Literal(s) => write!(&mut out, "{}", s)?,
+                Piece::Metric(name) => write!(&mut out, "{}", count.metric(name).unwrap_or(0))?,
+                Piece::Path => {
+                    if let Some(ref path) = count.path {
+                        write_path(opt, path, &mut out)?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[test]
+fn test_template() {
+    use crate::count::{Counter, Strategy};
+    use std::convert::TryFrom;
+
+    let template = Template::parse("{lines}\\t{words} {path}").unwrap();
+    let mut c = Counts::new("foo");
+    c.lines = 1;
+    c.words = 2;
+
+    let mut out = Vec::new();
+    template.render(&Opt::default(), &c, &mut out).unwrap();
+    assert_eq!(out, b"1\t2 foo");
+
+    assert!(Template::parse("{nope}").is_err());
+    assert_eq!(
+        Template::parse("{lines").unwrap_err(),
+        "unterminated placeholder {lines"
+    );
+
+    let opt = Opt::default();
+    let capability = Strategy::try_from(&opt).unwrap().capabilities();
+    assert!(template.validate(&opt, &capability).is_err());
+
+    // Bytes are only valid because the strategy counting -m has them too
+    let bytes = Template::parse("{bytes}").unwrap();
+    let opt = Opt::builder().chars(true).build();
+    let capability = Strategy::try_from(&opt).unwrap().capabilities();
+    assert!(bytes.validate(&opt, &capability).is_ok());
+    assert!(bytes.validate(&opt, &Capability::default()).is_err());
+}
+
+/// A column to order rows by, as given to `--sort`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SortKey {
+    Path,
+    Metric(&'static str),
+}
+
+impl SortKey {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        if s == "path" {
+            return Ok(SortKey::Path);
+        }
+
+        Counts::default()
+            .metrics(&Opt::default())
+            .into_iter()
+            .find(|(metric, _, _)| *metric == s)
+            .map(|(metric, _, _)| SortKey::Metric(metric))
+            .ok_or_else(|| format!("unknown sort key {}", s))
+    }
+
+    /// Sort rows in ascending order, or descending with `reverse`, keeping
+    /// ties in their original order
+    pub fn sort(&self, rows: &mut [Counts], reverse: bool) {
+        rows.sort_by(|a, b| {
+            let ord = match self {
+                SortKey::Path => a.path.cmp(&b.path),
+                SortKey::Metric(name) => a.metric(name).cmp(&b.metric(name)),
+            };
+
+            if reverse {
+                ord.reverse()
+            } else {
+                ord
+            }
+        });
+    }
+}
+
+#[test]
+fn test_sort_key() {
+    let mut rows = vec![Counts::new("b"), Counts::new("a"), Counts::new("c")];
+    rows[0].bytes = 2;
+    rows[1].bytes = 3;
+    rows[2].bytes = 2;
+
+    SortKey::parse("bytes").unwrap().sort(&mut rows, true);
+    let paths: Vec<_> = rows
+        .iter()
+        .filter_map(|c| c.path.as_ref()?.to_str())
+        .collect();
+    assert_eq!(paths, ["a", "b", "c"]);
+
+    SortKey::parse("path").unwrap().sort(&mut rows, true);
+    assert_eq!(rows[0].path, Some("c".into()));
+
+    assert!(SortKey::parse("nope").is_err());
+}
+
+/// Write an error with an input as `path: error`, or with `--json-errors` as a
+/// JSON object of both on a line of its own.
+pub fn print_error<W: Write>(
+    opt: &Opt,
+    path: &Path,
+    error: &dyn fmt::Display,
+    mut out: W,
+) -> io::Result<()> {
+    if opt.json_errors {
+        write!(&mut out, "{{\"path\":")?;
+        write_json_str(&mut out, &path.to_string_lossy())?;
+        write!(&mut out, ",\"error\":")?;
+        write_json_str(&mut out, &error.to_string())?;
+        writeln!(&mut out, "}}")
+    } else {
+        writeln!(&mut out, "{}: {}", path.display(), error)
+    }
+}
+
+#[test]
+fn test_print_error() {
+    let error = io::Error::new(io::ErrorKind::NotFound, "not \"found\"");
+
+    let mut out = vec![];
+    print_error(&Opt::default(), Path::new("a b"), &error, &mut out).unwrap();
+    assert_eq!(out, b"a b: not \"found\"\n");
+
+    let opt = Opt {
+        json_errors: true,
+        ..Opt::default()
+    };
+    let mut out = vec![];
+    print_error(&opt, Path::new("a\tb"), &error, &mut out).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "{\"path\":\"a\\tb\",\"error\":\"not \\\"found\\\"\"}\n"
+    );
+}
+
+/// Writes a sequence of `Counts` rows, handling any framing the output
+/// format needs around them.
+///
+/// Columnar output for more than one input is buffered until `finish`, so
+/// every row can be aligned to the widest value seen, unless `--wc-compat`
+/// fixes the width as wc does.  Output is also
+/// buffered to sort it with `--sort`, and the total always comes last.
+///
+/// With `--quiet`, nothing is printed at all.
+pub struct Printer<'a, W: Write> {
+    opt: &'a Opt,
+    format: Format,
+    out: W,
+    rows: usize,
+    width: usize,
+    buffered: Option<Vec<Counts>>,
+    total: Option<Counts>,
+    // The largest value of each column, to pick out with --color
+    highlight: Vec<u64>,
+    baseline: Option<Baseline>,
+}
+
+impl<'a, W: Write> Printer<'a, W> {
+    pub fn new(opt: &'a Opt, out: W) -> Self {
+        let format = Format::from(opt);
+
+        Self {
+            opt,
+            format,
+            out,
+            rows: 0,
+            width: std::cmp::max(7, Self::label_width(opt)),
+            buffered: if (format == Format::Columns && opt.input.len() > 1 && !opt.wc_compat)
+                || opt.sort.is_some()
+            {
+                Some(vec![])
+            } else {
+                None
+            },
+            total: None,
+            highlight: vec![],
+            baseline: None,
+        }
+    }
+
+    /// Print counts as differences from `baseline`, for `--baseline`
+    pub fn with_baseline(mut self, baseline: Baseline) -> Self {
+        self.baseline = Some(baseline);
+        self
+    }
+
+    // Empty rows for files in the baseline that haven't been printed, to list
+    // their counts as removed
+    fn removed_rows(&self) -> Vec<Counts> {
+        self.baseline
+            .iter()
+            .flat_map(Baseline::removed_paths)
+            .map(Counts::new)
+            .collect()
+    }
+
+    fn row_width(&self, count: &Counts) -> usize {
+        let base = match (&self.baseline, &count.path) {
+            (Some(baseline), Some(path)) => baseline.get(path),
+            _ => None,
+        };
+
+        match base {
+            Some(base) => count.delta_width(self.opt, base),
+            None => count.width(self.opt),
+        }
+    }
+
+    /// The width needed to fit the header labels, if there are any
+    fn label_width(opt: &Opt) -> usize {
+        if opt.header {
+            Counts::labels(opt)
+                .iter()
+                .map(|l| l.len())
+                .max()
+                .unwrap_or(1)
+        } else {
+            1
+        }
+    }
+
+    pub fn print(&mut self, count: &Counts) -> io::Result<()> {
+        if self.opt.quiet {
+            return Ok(());
+        }
+
+        if let (Some(baseline), Some(path)) = (&mut self.baseline, &count.path) {
+            baseline.mark_seen(path);
+        }
+
+        match self.buffered {
+            Some(ref mut buffered) => {
+                buffered.push(count.clone());
+
+                // Only the top rows are needed, so don't hold on to the rest
+                if let (Some(key), Some(top)) = (&self.opt.sort, self.opt.top) {
+                    if buffered.len() >= top.saturating_mul(2).max(1024) {
+                        key.sort(buffered, self.opt.reverse);
+                        buffered.drain(..buffered.len() - top);
+                    }
+                }
+
+                Ok(())
+            }
+            None => self.write_row(count),
+        }
+    }
+
+    /// Print a total, which follows every other row
+    pub fn print_total(&mut self, total: &Counts) -> io::Result<()> {
+        if self.opt.quiet {
+            Ok(())
+        } else if self.buffered.is_some() {
+            self.total = Some(total.clone());
+            Ok(())
+        } else {
+            for count in self.removed_rows() {
+                self.write_row(&count)?;
+            }
+            self.write_row(total)
+        }
+    }
+
+    fn write_row(&mut self, count: &Counts) -> io::Result<()> {
+        if self.opt.header && self.rows == 0 {
+            Counts::print_header(self.opt, self.width, &mut self.out)?;
+        }
+
+        match self.format {
+            Format::Json => {
+                let sep = if self.rows == 0 { "[\n" } else { ",\n" };
+                write!(&mut self.out, "{}  ", sep)?;
+                count.print_json(self.opt, &mut self.out)?;
+            }
+            Format::Csv if self.rows == 0 => {
+                for (name, _, _) in count.csv_columns(self.opt) {
+                    write!(&mut self.out, "{},", name)?;
+                }
+                write!(&mut self.out, "path")?;
+                end_record(self.opt, &mut self.out)?;
+                count.print(self.opt, &mut self.out)?;
+            }
+            _ => {
+                let base = match (&mut self.baseline, &count.path) {
+                    (Some(baseline), Some(path)) => {
+                        baseline.mark_seen(path);
+                        baseline.get(path)
+                    }
+                    _ => None,
+                };
+
+                match base {
+                    Some(base) => count.print_delta(self.opt, self.width, base, &mut self.out)?,
+                    None => count.print_highlighted(
+                        self.opt,
+                        self.width,
+                        &self.highlight,
+                        &mut self.out,
+                    )?,
+                }
+            }
+        }
+
+        // Each line is a complete record, so let consumers have it right away
+        if self.format == Format::JsonLines {
+            self.out.flush()?;
+        }
+
+        self.rows += 1;
+        Ok(())
+    }
+
+    // The largest value of each enabled column across `rows`
+    fn column_maxima(opt: &Opt, rows: &[Counts]) -> Vec<u64> {
+        let mut maxima: Vec<u64> = vec![];
+
+        for row in rows {
+            let values = row
+                .metrics(opt)
+                .into_iter()
+                .filter(|(_, enabled, _)| *enabled)
+                .map(|(_, _, value)| value);
+
+            for (i, value) in values.enumerate() {
+                match maxima.get_mut(i) {
+                    Some(max) => *max = (*max).max(value),
+                    None => maxima.push(value),
+                }
+            }
+        }
+
+        maxima
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        self.write_remaining(true)
+    }
+
+    /// Print the rows held back for alignment or sorting when counting is
+    /// interrupted, leaving out the total and `--baseline` rows for files not
+    /// reached.
+    pub fn finish_interrupted(&mut self) -> io::Result<()> {
+        self.write_remaining(false)
+    }
+
+    fn write_remaining(&mut self, complete: bool) -> io::Result<()> {
+        if self.opt.quiet {
+            return Ok(());
+        }
+
+        if let Some(mut buffered) = self.buffered.take() {
+            if let Some(ref key) = self.opt.sort {
+                key.sort(&mut buffered, self.opt.reverse);
+
+                // The top rows are the last ones, either the largest or the smallest with
+                // --reverse
+                if let Some(top) = self.opt.top {
+                    buffered.drain(..buffered.len().saturating_sub(top));
+                }
+            }
+            if self.opt.color == ColorChoice::Always && buffered.len() > 1 {
+                self.highlight = Self::column_maxima(self.opt, &buffered);
+            }
+
+            if complete {
+                buffered.extend(self.removed_rows());
+            }
+            buffered.extend(self.total.take());
+
+            if self.format == Format::Columns && !self.opt.wc_compat {
+                self.width = buffered
+                    .iter()
+                    .map(|count| self.row_width(count))
+                    .fold(Self::label_width(self.opt), std::cmp::max);
+            }
+
+            for count in &buffered {
+                self.write_row(count)?;
+            }
+        } else if complete {
+            for count in self.removed_rows() {
+                self.write_row(&count)?;
+            }
+        }
+
+        if self.format == Format::Json {
+            if self.rows == 0 {
+                writeln!(&mut self.out, "[]")?;
+            } else {
+                writeln!(&mut self.out, "\n]")?;
+            }
+        }
+
+        self.out.flush()
+    }
+}
+
+#[test]
+fn test_wc_compat() {
+    // As printed by FreeBSD's wc
+    let opt = Opt {
+        lines: true,
+        words: true,
+        bytes: true,
+        wc_compat: true,
+        input: vec!["a".into(), "b".into()],
+        ..Opt::default()
+    };
+    let mut a = Counts::new("a");
+    a.lines = 3;
+    a.words = 6;
+    a.bytes = 29;
+    let mut b = Counts::new("b");
+    b.lines = 12;
+    b.words = 1234;
+    b.bytes = 123_456_789;
+    let total = Counts::new("total").merged(&a).merged(&b);
+
+    let mut out = vec![];
+    let mut printer = Printer::new(&opt, &mut out);
+    printer.print(&a).unwrap();
+    printer.print(&b).unwrap();
+    printer.print_total(&total).unwrap();
+    printer.finish().unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "       3       6      29 a\n      12    1234 123456789 b\n      15    1240 123456818 total\n"
+    );
+
+    let opt = Opt {
+        lines: true,
+        wc_compat: true,
+        ..Opt::default()
+    };
+    let mut out = vec![];
+    let mut printer = Printer::new(&opt, &mut out);
+    printer
+        .print(&Counts {
+            lines: 3,
+            ..Counts::default()
+        })
+        .unwrap();
+    printer.finish().unwrap();
+    assert_eq!(out, b"       3\n");
+}
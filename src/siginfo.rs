@@ -7,7 +7,7 @@ mod sig {
 
     static SIGINFO_RECEIVED: AtomicUsize = AtomicUsize::new(0);
     thread_local! {
-        static SIGINFO_GEN: Cell<usize> = Cell::new(0);
+        static SIGINFO_GEN: Cell<usize> = const { Cell::new(0) };
     }
 
     extern "C" fn trigger_signal(_: c_int) {
@@ -26,31 +26,205 @@ mod sig {
         })
     }
 
-    pub fn hook_signal() {
+    // The signals --siginfo-signal can choose from, without their SIG prefix
+    const SIGNALS: &[(&str, c_int)] = &[
+        ("HUP", libc::SIGHUP),
+        ("QUIT", libc::SIGQUIT),
+        ("ALRM", libc::SIGALRM),
+        ("USR1", libc::SIGUSR1),
+        ("USR2", libc::SIGUSR2),
+        ("WINCH", libc::SIGWINCH),
+        #[cfg(any(
+            target_os = "macos",
+            target_os = "ios",
+            target_os = "freebsd",
+            target_os = "dragonfly",
+            target_os = "openbsd",
+            target_os = "netbsd"
+        ))]
+        ("INFO", libc::SIGINFO),
+    ];
+
+    /// Parse a signal name such as USR2 or SIGUSR2, ignoring case
+    pub fn parse_signal(name: &str) -> Result<i32, String> {
+        let upper = name.to_ascii_uppercase();
+        let bare = upper.strip_prefix("SIG").unwrap_or(&upper);
+
+        SIGNALS
+            .iter()
+            .find(|(n, _)| *n == bare)
+            .map(|&(_, sig)| sig)
+            .ok_or_else(|| {
+                let names: Vec<_> = SIGNALS.iter().map(|(n, _)| *n).collect();
+                format!(
+                    "unsupported signal {}, expected one of {}",
+                    name,
+                    names.join(", ")
+                )
+            })
+    }
+
+    /// Print progress on `sig`, or by default SIGINFO where there is one, and
+    /// SIGUSR1
+    pub fn hook_signal(sig: Option<i32>) {
         unsafe {
+            if let Some(sig) = sig {
+                signal(sig, get_handler());
+                return;
+            }
+
             #[cfg(any(
                 target_os = "macos",
                 target_os = "ios",
                 target_os = "freebsd",
                 target_os = "dragonfly",
                 target_os = "openbsd",
-                target_os = "netbsd",
-                target_os = "bitrig"
+                target_os = "netbsd"
             ))]
             signal(libc::SIGINFO, get_handler());
 
             signal(libc::SIGUSR1, get_handler());
         }
     }
+
+    // The first Ctrl-C asks for a graceful stop, a second one quits at once
+    extern "C" fn trigger_interrupt(_: c_int) {
+        if super::INTERRUPTED.swap(true, Ordering::AcqRel) {
+            unsafe { libc::_exit(super::INTERRUPTED_EXIT_CODE) }
+        }
+    }
+
+    // Without SA_RESTART, so a read blocked on a terminal fails with EINTR
+    // rather than waiting for more input
+    pub fn hook_interrupt() {
+        unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction =
+                trigger_interrupt as extern "C" fn(c_int) as *mut c_void as sighandler_t;
+            libc::sigemptyset(&mut action.sa_mask);
+            libc::sigaction(libc::SIGINT, &action, std::ptr::null_mut());
+        }
+    }
 }
 
-#[cfg(not(unix))]
+#[cfg(windows)]
+mod sig {
+    use std::cell::Cell;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread_local;
+    use winapi::shared::minwindef::{BOOL, DWORD, FALSE, TRUE};
+    use winapi::um::consoleapi::SetConsoleCtrlHandler;
+    use winapi::um::wincon::{CTRL_BREAK_EVENT, CTRL_C_EVENT};
+
+    static SIGINFO_RECEIVED: AtomicUsize = AtomicUsize::new(0);
+    thread_local! {
+        static SIGINFO_GEN: Cell<usize> = const { Cell::new(0) };
+    }
+
+    // Ctrl-Break stands in for SIGINFO, anything else goes to the default handler
+    unsafe extern "system" fn ctrl_handler(ctrl_type: DWORD) -> BOOL {
+        if ctrl_type == CTRL_BREAK_EVENT {
+            SIGINFO_RECEIVED.fetch_add(1, Ordering::Release);
+            TRUE
+        } else {
+            FALSE
+        }
+    }
+
+    // The first Ctrl-C asks for a graceful stop, a second goes on to the
+    // default handler and quits at once
+    unsafe extern "system" fn interrupt_handler(ctrl_type: DWORD) -> BOOL {
+        if ctrl_type == CTRL_C_EVENT && !super::INTERRUPTED.swap(true, Ordering::AcqRel) {
+            TRUE
+        } else {
+            FALSE
+        }
+    }
+
+    pub fn check_signal() -> bool {
+        SIGINFO_GEN.with(|gen| {
+            let current = SIGINFO_RECEIVED.load(Ordering::Acquire);
+            let prev = gen.replace(current);
+            prev != current
+        })
+    }
+
+    pub fn parse_signal(name: &str) -> Result<i32, String> {
+        Err(format!(
+            "unsupported signal {}, progress is only printed on Ctrl-Break",
+            name
+        ))
+    }
+
+    pub fn hook_signal(_: Option<i32>) {
+        unsafe {
+            SetConsoleCtrlHandler(Some(ctrl_handler), TRUE);
+        }
+    }
+
+    pub fn hook_interrupt() {
+        unsafe {
+            SetConsoleCtrlHandler(Some(interrupt_handler), TRUE);
+        }
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
 mod sig {
     pub fn check_signal() -> bool {
         false
     }
 
-    pub fn hook_signal() {}
+    pub fn parse_signal(name: &str) -> Result<i32, String> {
+        Err(format!(
+            "unsupported signal {}, signals aren't supported on this platform",
+            name
+        ))
+    }
+
+    pub fn hook_signal(_: Option<i32>) {}
+
+    pub fn hook_interrupt() {}
 }
 
 pub use sig::*;
+
+use std::cell::Cell;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+/// The exit status after Ctrl-C, as a shell reports a process killed by SIGINT
+pub const INTERRUPTED_EXIT_CODE: i32 = 130;
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Whether Ctrl-C has been pressed since `hook_interrupt`
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::Acquire)
+}
+
+thread_local! {
+    static LAST_REPORT: Cell<Option<Instant>> = const { Cell::new(None) };
+}
+
+/// Returns true at most once every `secs` seconds per thread, or never if 0
+pub fn check_interval(secs: u64) -> bool {
+    if secs == 0 {
+        return false;
+    }
+
+    LAST_REPORT.with(|last| {
+        let now = Instant::now();
+        match last.get() {
+            Some(t) if now.duration_since(t) < Duration::from_secs(secs) => false,
+            Some(_) => {
+                last.set(Some(now));
+                true
+            }
+            None => {
+                last.set(Some(now));
+                false
+            }
+        }
+    })
+}
@@ -0,0 +1,88 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::hash::Hasher;
+use std::io::{self, Read};
+
+use memchr::memchr_iter;
+
+/// Collects a 64-bit hash of every distinct line ending in a delimiter,
+/// without it.
+///
+/// Memory use grows with the number of distinct lines, at somewhat more than
+/// 8 bytes each, rather than with their length.  Two different lines sharing
+/// a hash would be counted once, but that's vanishingly unlikely.
+#[derive(Debug)]
+pub struct UniqueLines {
+    hashes: HashSet<u64>,
+    hasher: DefaultHasher,
+    pending: bool,
+    delimiter: u8,
+}
+
+impl UniqueLines {
+    pub fn new(delimiter: u8) -> Self {
+        Self {
+            hashes: HashSet::new(),
+            hasher: DefaultHasher::new(),
+            pending: false,
+            delimiter,
+        }
+    }
+
+    pub fn feed(&mut self, buf: &[u8]) {
+        let mut start = 0;
+        for pos in memchr_iter(self.delimiter, buf) {
+            self.hasher.write(&buf[start..pos]);
+            self.hashes.insert(self.hasher.finish());
+            self.hasher = DefaultHasher::new();
+            self.pending = false;
+            start = pos + 1;
+        }
+
+        if start < buf.len() {
+            self.hasher.write(&buf[start..]);
+            self.pending = true;
+        }
+    }
+
+    /// Take the hashes seen, including any final unterminated line
+    pub fn finish(mut self) -> HashSet<u64> {
+        if self.pending {
+            self.hashes.insert(self.hasher.finish());
+        }
+
+        self.hashes
+    }
+}
+
+/// Passes reads through while feeding them to a `UniqueLines`
+pub struct UniqueReader<'a, R> {
+    inner: R,
+    lines: &'a mut UniqueLines,
+}
+
+impl<'a, R: Read> UniqueReader<'a, R> {
+    pub fn new(inner: R, lines: &'a mut UniqueLines) -> Self {
+        Self { inner, lines }
+    }
+}
+
+impl<R: Read> Read for UniqueReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        self.lines.feed(&buf[..len]);
+        Ok(len)
+    }
+}
+
+#[test]
+fn test_unique_lines() {
+    let mut lines = UniqueLines::new(b'\n');
+    lines.feed(b"foo\nbar\nfo");
+    lines.feed(b"o\nbar\nbaz");
+    assert_eq!(lines.finish().len(), 3);
+
+    let mut lines = UniqueLines::new(0);
+    lines.feed(b"foo\0bar\0foo\0");
+    assert_eq!(lines.finish().len(), 2);
+}
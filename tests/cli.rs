@@ -0,0 +1,952 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+fn cw(args: &[&str], stdin: &[u8]) -> Output {
+    cw_env(args, &[], stdin)
+}
+
+// Run with only the given color-related environment variables set
+fn cw_env(args: &[&str], env: &[(&str, &str)], stdin: &[u8]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_cw"))
+        .args(args)
+        .env_remove("NO_COLOR")
+        .env_remove("CLICOLOR_FORCE")
+        .envs(env.iter().copied())
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("spawn cw");
+
+    // Written from another thread, so input passed through to stdout can't
+    // fill its pipe while cw waits for more
+    let mut input = child.stdin.take().expect("stdin");
+    let stdin = stdin.to_vec();
+    let writer = std::thread::spawn(move || input.write_all(&stdin));
+
+    let out = child.wait_with_output().expect("wait for cw");
+
+    // cw may exit without reading its input, such as on a usage error
+    match writer.join().expect("stdin writer") {
+        Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => (),
+        ret => ret.expect("write stdin"),
+    }
+    out
+}
+
+// Create a directory of files in the temp dir, replacing any left from before
+fn temp_tree(name: &str, files: &[(&str, &str)]) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("cw-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    for (path, contents) in files {
+        let path = dir.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, contents).unwrap();
+    }
+    dir
+}
+
+// Each output row as its first count and the path relative to `dir`
+fn rows(out: &Output, dir: &Path) -> Vec<(u64, String)> {
+    String::from_utf8_lossy(&out.stdout)
+        .lines()
+        .map(|row| {
+            let mut fields = row.split_whitespace();
+            let count = fields.next().unwrap().parse().unwrap();
+            let path = fields.last().unwrap();
+            let path = Path::new(path).strip_prefix(dir).unwrap_or(Path::new(path));
+            (count, path.to_string_lossy().into_owned())
+        })
+        .collect()
+}
+
+#[test]
+fn test_files_from_stdin() {
+    let expected = cw(&["Cargo.toml", "README.md"], b"");
+    let out = cw(&["--files-from", "-"], b"Cargo.toml\nREADME.md\n");
+    assert!(out.status.success());
+    assert_eq!(out.stdout, expected.stdout);
+
+    // An empty list means nothing to count, not a count of stdin
+    let out = cw(&["--files-from", "-"], b"");
+    assert!(out.status.success());
+    assert_eq!(out.stdout, b"");
+}
+
+#[test]
+fn test_files_list_delimiter() {
+    let expected = cw(&["Cargo.toml", "README.md"], b"");
+    let out = cw(
+        &["--files-list", "-", "--files-delimiter", "\\t"],
+        b"Cargo.toml\tREADME.md",
+    );
+    assert!(out.status.success());
+    assert_eq!(out.stdout, expected.stdout);
+}
+
+#[test]
+fn test_unique_paths() {
+    let expected = cw(&["Cargo.toml", "README.md"], b"");
+    let out = cw(
+        &[
+            "--unique-paths",
+            "Cargo.toml",
+            "README.md",
+            "./Cargo.toml",
+            "--files-from",
+            "-",
+        ],
+        b"README.md\n",
+    );
+    assert!(out.status.success());
+    assert_eq!(out.stdout, expected.stdout);
+}
+
+#[test]
+fn test_threaded_order() {
+    let dir = std::env::temp_dir().join(format!("cw-order-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    // The first file is by far the largest, so later ones finish first
+    let mut paths = vec![];
+    for i in 0..200 {
+        let path = dir.join(format!("{:03}", i));
+        let size = if i == 0 { 8 * 1024 * 1024 } else { i };
+        std::fs::write(&path, "a b\n".repeat(size)).unwrap();
+        paths.push(path.to_str().unwrap().to_owned());
+    }
+
+    let args: Vec<&str> = paths.iter().map(String::as_str).collect();
+    let expected = cw(&args, b"");
+
+    let mut threaded_args = vec!["--threads", "16"];
+    threaded_args.extend(&args);
+    let threaded = cw(&threaded_args, b"");
+
+    let mut windowed_args = vec!["--threads", "16", "--reorder-window", "4"];
+    windowed_args.extend(&args);
+    let windowed = cw(&windowed_args, b"");
+
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert!(threaded.status.success());
+
+    let rows: Vec<_> = String::from_utf8_lossy(&threaded.stdout)
+        .lines()
+        .filter_map(|line| line.split_whitespace().last().map(str::to_owned))
+        .collect();
+    assert_eq!(rows[..paths.len()], paths[..]);
+    assert_eq!(
+        String::from_utf8_lossy(&threaded.stdout),
+        String::from_utf8_lossy(&expected.stdout)
+    );
+    assert_eq!(windowed.stdout, expected.stdout);
+}
+
+#[test]
+fn test_threads_per_cpu() {
+    let files: Vec<_> = (0..20)
+        .map(|i| (format!("{:02}", i), "one two\n".repeat(i * 100)))
+        .collect();
+    let files: Vec<_> = files
+        .iter()
+        .map(|(n, c)| (n.as_str(), c.as_str()))
+        .collect();
+    let dir = temp_tree("threads", &files);
+    let dir = dir.to_str().unwrap();
+
+    // 0 picks a thread per CPU, which mustn't change the counts or their order
+    let one = cw(&["--threads", "1", "-r", dir], b"");
+    let per_cpu = cw(&["--threads", "0", "-r", dir], b"");
+    assert!(per_cpu.status.success());
+    assert_eq!(per_cpu.stdout, one.stdout);
+
+    let input = "a b\n".repeat(500_000);
+    let one = cw(&["--threads", "1", "-l"], input.as_bytes());
+    let per_cpu = cw(&["--threads", "0", "-l"], input.as_bytes());
+    assert_eq!(per_cpu.stdout, one.stdout);
+    assert_eq!(per_cpu.stdout, b"500000\n");
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_progress() {
+    let plain = cw(&["-l", "Cargo.toml", "README.md"], b"");
+    let out = cw(&["-l", "--progress", "Cargo.toml", "README.md"], b"");
+
+    // stderr isn't a terminal here, so there's nothing to redraw
+    assert!(out.status.success());
+    assert_eq!(out.stdout, plain.stdout);
+    assert_eq!(out.stderr, b"");
+}
+
+#[test]
+fn test_progress_interval() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_cw"))
+        .args(["-l", "--progress-interval", "1"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("spawn cw");
+
+    // Progress is printed after the first read once the interval has passed
+    let mut stdin = child.stdin.take().unwrap();
+    stdin.write_all(b"one\n").unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(1500));
+    stdin.write_all(b"two\n").unwrap();
+    drop(stdin);
+
+    let out = child.wait_with_output().unwrap();
+    assert!(out.status.success());
+    assert_eq!(out.stdout, b"2\n");
+    assert_eq!(out.stderr, b"2\n");
+
+    // 0, the default, never prints progress
+    let out = cw(&["-l", "--progress-interval", "0"], b"one\n");
+    assert_eq!(out.stderr, b"");
+}
+
+#[test]
+fn test_aggregate() {
+    let dir = temp_tree("aggregate", &[("a", "1\n"), ("b", "1\n2\n")]);
+    let (a, b) = (dir.join("a"), dir.join("b"));
+    let (a, b) = (a.to_str().unwrap(), b.to_str().unwrap());
+
+    let out = cw(&["-l", "--aggregate", a, b], b"");
+    assert!(out.status.success());
+    assert_eq!(rows(&out, &dir), [(3, "a".to_string())]);
+
+    let out = cw(
+        &["-l", "--aggregate", "--aggregate-name", "both", a, b],
+        b"",
+    );
+    assert_eq!(rows(&out, &dir), [(3, "both".to_string())]);
+
+    // It replaces the total, so can't be combined with options controlling it
+    let out = cw(&["-l", "--aggregate", "--no-total", a, b], b"");
+    assert!(!out.status.success());
+    let out = cw(&["-l", "--aggregate-name", "both", a, b], b"");
+    assert!(!out.status.success());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_exclude() {
+    let expected = cw(&["src/lib.rs"], b"");
+    let out = cw(
+        &[
+            "-r",
+            "src",
+            "--exclude",
+            "*[!b].rs",
+            "--exclude",
+            "src/args*",
+        ],
+        b"",
+    );
+    assert!(out.status.success());
+    assert_eq!(out.stdout, expected.stdout);
+}
+
+#[test]
+fn test_quiet() {
+    let out = cw(&["-q", "--json", "Cargo.toml", "README.md"], b"");
+    assert!(out.status.success());
+    assert_eq!(out.stdout, b"");
+
+    let out = cw(&["--quiet", "Cargo.toml", "does-not-exist"], b"");
+    assert!(!out.status.success());
+    assert_eq!(out.stdout, b"");
+    assert!(!out.stderr.is_empty());
+}
+
+#[test]
+fn test_color_env() {
+    let files = ["Cargo.toml", "README.md"];
+    let escaped = |out: &Output| out.stdout.contains(&b'\x1b');
+
+    assert!(!escaped(&cw_env(&files, &[], b"")));
+    assert!(escaped(&cw_env(&files, &[("CLICOLOR_FORCE", "1")], b"")));
+    assert!(!escaped(&cw_env(&files, &[("CLICOLOR_FORCE", "0")], b"")));
+
+    let out = cw_env(
+        &["--progress", "Cargo.toml", "README.md"],
+        &[("NO_COLOR", "1"), ("CLICOLOR_FORCE", "1")],
+        b"",
+    );
+    assert!(!escaped(&out));
+    assert!(!out.stderr.contains(&b'\x1b'));
+
+    let out = cw_env(&["--color=always", "Cargo.toml"], &[("NO_COLOR", "1")], b"");
+    assert!(escaped(&out));
+}
+
+#[test]
+fn test_stdin_name() {
+    let out = cw(&["-l", "README.md", "-", "Cargo.toml"], b"a\nb\n");
+    assert!(out.status.success());
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    let paths: Vec<_> = stdout
+        .lines()
+        .map(|l| l.split_whitespace().last().unwrap())
+        .collect();
+    assert_eq!(paths, ["README.md", "-", "Cargo.toml", "total"]);
+    assert!(stdout.lines().any(|l| l.split_whitespace().eq(["2", "-"])));
+
+    let out = cw(
+        &["-l", "--stdin-name", "input", "--json", "Cargo.toml", "-"],
+        b"a\n",
+    );
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    assert!(stdout.contains(r#"{"path":"input","lines":1}"#));
+
+    let out = cw(&["-l", "--stdin-name", "input"], b"a\n");
+    assert_eq!(out.stdout, b"       1 input\n");
+}
+
+#[test]
+fn test_dash_is_stdin() {
+    let out = cw(&["-"], b"hi\n");
+    assert!(out.status.success());
+    assert_eq!(out.stdout, b"       1       1       3 -\n");
+
+    let expected = cw(&["Cargo.toml", "-", "README.md"], b"hi\n");
+    let out = cw(&["--threads", "4", "Cargo.toml", "-", "README.md"], b"hi\n");
+    assert!(out.status.success());
+    assert_eq!(out.stdout, expected.stdout);
+
+    let out = cw(&["-", "-"], b"hi\n");
+    assert!(!out.status.success());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("can only be read once"));
+
+    let out = cw(&["--files-from", "-"], b"-\n");
+    assert!(!out.status.success());
+}
+
+#[test]
+fn test_baseline() {
+    let path = std::env::temp_dir().join(format!("cw-baseline-{}.csv", std::process::id()));
+    std::fs::write(
+        &path,
+        "lines,words,chars,bytes,path\n1,1,,5,-\n3,3,,9,gone\n4,4,,14,total\n",
+    )
+    .unwrap();
+    let baseline = path.to_str().unwrap();
+
+    let out = cw(&["--baseline", baseline, "-"], b"one\ntwo\n");
+    std::fs::remove_file(&path).unwrap();
+    assert!(out.status.success());
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    let rows: Vec<Vec<&str>> = stdout
+        .lines()
+        .map(|l| l.split_whitespace().collect())
+        .collect();
+    assert_eq!(rows, [["+1", "+1", "+3", "-"], ["-3", "-3", "-9", "gone"]]);
+
+    let out = cw(&["--baseline", "does-not-exist.json", "-"], b"");
+    assert!(!out.status.success());
+}
+
+#[test]
+fn test_limit() {
+    let out = cw(&["-l", "--limit", "lines=2", "-"], b"a\nb\n");
+    assert!(out.status.success());
+    assert!(out.stderr.is_empty());
+
+    let out = cw(
+        &["-lw", "--limit", "lines=5", "--limit", "words=1", "-"],
+        b"a b\n",
+    );
+    assert_eq!(out.status.code(), Some(1));
+    assert_eq!(out.stdout, b"       1       2 -\n");
+    assert_eq!(out.stderr, b"-: 2 words is over the limit of 1\n");
+
+    let out = cw(&["-l", "--limit", "words=1", "-"], b"");
+    assert!(!out.status.success());
+    assert!(out.stdout.is_empty());
+}
+
+#[test]
+fn test_posix_lines() {
+    let out = cw(&["-l", "--count-partial-line", "-"], b"foo\nbar");
+    assert_eq!(out.stdout, b"       2 -\n");
+
+    // Whichever comes last wins
+    let out = cw(
+        &["-l", "--count-partial-line", "--posix-lines", "-"],
+        b"foo\nbar",
+    );
+    assert_eq!(out.stdout, b"       1 -\n");
+
+    let out = cw(
+        &["-l", "--posix-lines", "--count-partial-line", "-"],
+        b"foo\nbar",
+    );
+    assert_eq!(out.stdout, b"       2 -\n");
+}
+
+#[test]
+fn test_cache() {
+    let dir = std::env::temp_dir();
+    let input = dir.join(format!("cw-cli-cache-input-{}", std::process::id()));
+    let cache = dir.join(format!("cw-cli-cache-{}", std::process::id()));
+    std::fs::write(&input, "a\nb\n").unwrap();
+    let args = [
+        "-l",
+        "--cache",
+        cache.to_str().unwrap(),
+        input.to_str().unwrap(),
+    ];
+
+    let first = cw(&args, b"");
+    assert!(first.status.success());
+
+    // Prove the second run is served from the cache by tampering with it
+    let saved = std::fs::read_to_string(&cache).unwrap();
+    let tampered = saved.replacen(" 2 ", " 42 ", 1);
+    assert_ne!(saved, tampered);
+    std::fs::write(&cache, tampered).unwrap();
+
+    let second = cw(&args, b"");
+    let stdout = String::from_utf8(second.stdout).unwrap();
+    assert!(stdout.trim_start().starts_with("42 "), "{}", stdout);
+
+    // Changing the file invalidates its entry
+    std::fs::write(&input, "a\nb\nc\n").unwrap();
+    let third = cw(&args, b"");
+    let stdout = String::from_utf8(third.stdout).unwrap();
+    assert!(stdout.trim_start().starts_with("3 "), "{}", stdout);
+
+    std::fs::remove_file(&input).unwrap();
+    std::fs::remove_file(&cache).unwrap();
+}
+
+#[test]
+fn test_json_errors() {
+    let out = cw(
+        &["--json-errors", "--json-lines", "-l", "does-not-exist", "-"],
+        b"a\n",
+    );
+    assert_eq!(out.status.code(), Some(1));
+    assert_eq!(
+        out.stdout,
+        b"{\"path\":\"-\",\"lines\":1}\n{\"path\":\"total\",\"lines\":1}\n"
+    );
+
+    let stderr = String::from_utf8(out.stderr).unwrap();
+    assert!(
+        stderr.starts_with("{\"path\":\"does-not-exist\",\"error\":\""),
+        "{}",
+        stderr
+    );
+    assert_eq!(stderr.lines().count(), 1);
+}
+
+#[test]
+fn test_recursive() {
+    let dir = temp_tree(
+        "recursive",
+        &[("b.txt", "1\n2\n"), ("a/c.txt", "3\n"), ("a/b/d.txt", "")],
+    );
+
+    let out = cw(&["-l", "-r", dir.to_str().unwrap()], b"");
+    assert!(out.status.success());
+    assert_eq!(
+        rows(&out, &dir),
+        [
+            (0, "a/b/d.txt".to_string()),
+            (1, "a/c.txt".to_string()),
+            (2, "b.txt".to_string()),
+            (3, "total".to_string()),
+        ]
+    );
+
+    // Without -r a directory can't be read
+    let out = cw(&["-l", dir.to_str().unwrap()], b"");
+    assert!(!out.status.success());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_glob() {
+    let dir = temp_tree(
+        "glob",
+        &[("a.txt", "1\n"), ("b.txt", "1\n2\n"), ("c.md", "1\n2\n3\n")],
+    );
+    let pattern = dir.join("*.txt");
+
+    let out = cw(&["-l", "--glob", pattern.to_str().unwrap()], b"");
+    assert!(out.status.success());
+    assert_eq!(
+        rows(&out, &dir),
+        [
+            (1, "a.txt".to_string()),
+            (2, "b.txt".to_string()),
+            (3, "total".to_string()),
+        ]
+    );
+
+    // Without --glob the pattern is taken literally
+    let out = cw(&["-l", pattern.to_str().unwrap()], b"");
+    assert!(!out.status.success());
+
+    let out = cw(&["-l", "--glob", dir.join("*.rs").to_str().unwrap()], b"");
+    assert!(!out.status.success());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("no matches found"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_gitignore() {
+    let dir = temp_tree(
+        "gitignore",
+        &[
+            (".gitignore", "*.log\n"),
+            ("a.txt", "1\n"),
+            ("b.log", "1\n2\n"),
+            (".hidden", "1\n2\n3\n"),
+        ],
+    );
+    // .gitignore only applies within a git repository
+    std::fs::create_dir(dir.join(".git")).unwrap();
+    let path = dir.to_str().unwrap();
+
+    let paths = |out: &Output| -> Vec<String> {
+        rows(out, &dir)
+            .into_iter()
+            .map(|(_, path)| path)
+            .filter(|path| path != "total")
+            .collect()
+    };
+
+    let out = cw(&["-l", "-r", path], b"");
+    assert_eq!(paths(&out), [".gitignore", ".hidden", "a.txt", "b.log"]);
+
+    let out = cw(&["-l", "-r", "--gitignore", path], b"");
+    assert!(out.status.success());
+    assert_eq!(paths(&out), ["a.txt"]);
+
+    let out = cw(&["-l", "-r", "--gitignore", "--hidden", path], b"");
+    assert_eq!(paths(&out), [".gitignore", ".hidden", "a.txt"]);
+
+    let out = cw(&["-l", "-r", "--hidden", path], b"");
+    assert!(!out.status.success());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(feature = "decompress")]
+#[test]
+fn test_decompress() {
+    let text = "one two\nthree\n".repeat(100);
+    let mut gz = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+    gz.write_all(text.as_bytes()).unwrap();
+    let gz = gz.finish().unwrap();
+    let zst = zstd::stream::encode_all(text.as_bytes(), 0).unwrap();
+
+    let plain = cw(&["-lwc"], text.as_bytes());
+    for input in &[&gz[..], &zst[..], text.as_bytes()] {
+        let out = cw(&["-lwc", "--decompress"], input);
+        assert!(out.status.success());
+        assert_eq!(out.stdout, plain.stdout);
+    }
+
+    // Without --decompress, compressed input is counted as it is
+    let out = cw(&["-c"], &gz);
+    assert_eq!(out.stdout, format!("{}\n", gz.len()).as_bytes());
+}
+
+#[cfg(not(feature = "decompress"))]
+#[test]
+fn test_decompress() {
+    let out = cw(&["-l", "--decompress"], b"one\n");
+    assert!(!out.status.success());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("decompress feature"));
+}
+
+#[test]
+fn test_strategy() {
+    let input = b"one two\nthree\n";
+    let chosen = cw(&["-l"], input);
+
+    // A strategy that can count more gives the same answer, only slower
+    let out = cw(&["-l", "--strategy", "WordsLinesLongest"], input);
+    assert!(out.status.success());
+    assert_eq!(out.stdout, chosen.stdout);
+
+    let out = cw(&["-w", "--strategy", "LinesOnly"], input);
+    assert!(!out.status.success());
+    assert_eq!(out.stderr, b"cw: strategy LinesOnly can't count words\n");
+
+    let out = cw(&["-l", "--strategy", "Nope"], input);
+    assert!(!out.status.success());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("unknown strategy Nope"));
+}
+
+#[test]
+fn test_frequency_output() {
+    let out = cw(&["--frequency"], b"a b a\n");
+    assert!(out.status.success());
+
+    // Words and their frequencies have a format of their own
+    for flag in &[
+        "--json",
+        "--json-lines",
+        "--csv",
+        "--tabs",
+        "--header",
+        "-0",
+    ] {
+        let out = cw(&["--frequency", flag], b"a b a\n");
+        assert!(!out.status.success(), "{}", flag);
+        assert!(String::from_utf8_lossy(&out.stderr).contains("cannot be used with"));
+    }
+    let out = cw(&["--frequency", "--format", "{lines}"], b"a b a\n");
+    assert!(String::from_utf8_lossy(&out.stderr).contains("cannot be used with"));
+
+    // As are options that only apply to counting
+    for args in &[
+        &["--sort", "lines"][..],
+        &["-H"],
+        &["--total", "never"],
+        &["-l"],
+    ] {
+        let out = cw(&[&["--frequency"], *args].concat(), b"a b a\n");
+        assert!(!out.status.success(), "{:?}", args);
+        assert!(String::from_utf8_lossy(&out.stderr).contains("cannot be used with"));
+    }
+}
+
+#[test]
+fn test_diff_output() {
+    let diff = b"--- a/f\n+++ b/f\n@@ -1 +1 @@\n-a\n+b\n";
+    let out = cw(&["--diff"], diff);
+    assert!(out.status.success());
+    assert_eq!(out.stdout, b"       1       1 f\n");
+
+    // Added and removed lines have a format of their own
+    for flag in &[
+        "--json",
+        "--json-lines",
+        "--csv",
+        "--tabs",
+        "--header",
+        "-0",
+    ] {
+        let out = cw(&["--diff", flag], diff);
+        assert!(!out.status.success(), "{}", flag);
+        assert!(String::from_utf8_lossy(&out.stderr).contains("cannot be used with"));
+    }
+    let out = cw(&["--diff", "--format", "{lines}"], diff);
+    assert!(String::from_utf8_lossy(&out.stderr).contains("cannot be used with"));
+
+    // As are options that only apply to counting
+    for args in &[
+        &["-H"][..],
+        &["--tee"],
+        &["--sort", "lines"],
+        &["--top", "1"],
+    ] {
+        let out = cw(&[&["--diff"], *args].concat(), diff);
+        assert!(!out.status.success(), "{:?}", args);
+        assert!(String::from_utf8_lossy(&out.stderr).contains("cannot be used with"));
+    }
+}
+
+#[test]
+fn test_du() {
+    let files = cw(&["-c", "-r", "src", "tests"], b"");
+    let dirs = cw(&["-c", "-r", "--du", "src", "tests"], b"");
+    assert!(dirs.status.success());
+
+    let files = String::from_utf8(files.stdout).unwrap();
+    let dirs = String::from_utf8(dirs.stdout).unwrap();
+    let rows: Vec<_> = dirs.lines().collect();
+    assert_eq!(rows.len(), 3, "{}", dirs);
+    assert!(rows[0].ends_with(" src"));
+    assert!(rows[1].ends_with(" tests"));
+    assert_eq!(rows[2].trim(), files.lines().last().unwrap().trim());
+
+    // Exclusions apply within directories too
+    let out = cw(&["-c", "-r", "--du", "tests", "--exclude", "*.rs"], b"");
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    assert_eq!(stdout.split_whitespace().next(), Some("0"), "{}", stdout);
+}
+
+#[test]
+fn test_count_files() {
+    for threads in &["1", "2"] {
+        let out = cw(
+            &[
+                "--count-files",
+                "--no-total",
+                "--threads",
+                threads,
+                "Cargo.toml",
+                "does-not-exist",
+                "README.md",
+            ],
+            b"",
+        );
+        assert!(!out.status.success());
+        assert_eq!(String::from_utf8(out.stdout).unwrap().lines().count(), 2);
+        let stderr = String::from_utf8(out.stderr).unwrap();
+        assert!(stderr.contains("cw: counted 2 of 3 files\n"), "{}", stderr);
+    }
+}
+
+#[test]
+fn test_tee() {
+    let input = b"one\ntwo\nthree\n";
+    let out = cw(&["--tee", "-l"], input);
+    assert!(out.status.success());
+    assert_eq!(out.stdout, input);
+    assert_eq!(out.stderr, b"3\n");
+
+    // Input is passed through whole even when counting stops early
+    let out = cw(&["--tee", "-l", "--max-lines", "1"], input);
+    assert_eq!(out.stdout, input);
+    assert_eq!(out.stderr, b"1\n");
+
+    // And when counting fails part way
+    let mut input = b"\xff\n".to_vec();
+    input.extend(vec![b'a'; 200_000]);
+    let out = cw(&["--tee", "--ascii"], &input);
+    assert!(!out.status.success());
+    assert_eq!(out.stdout, input);
+}
+
+#[test]
+fn test_line_delimiter_filters() {
+    // Line filters split lines on the same delimiter as the count
+    let input = b"foo\0bar\0foo\0";
+    let out = cw(&["-l", "--line-delimiter", "\\0", "--match", "foo"], input);
+    assert_eq!(out.stdout, b"2\n");
+
+    let out = cw(&["-l", "--line-delimiter", "\\0", "--unique"], input);
+    assert_eq!(out.stdout, b"       3       2\n");
+
+    let out = cw(
+        &[
+            "-c",
+            "--line-delimiter",
+            "\\r",
+            "--skip-lines",
+            "1",
+            "--max-lines",
+            "2",
+        ],
+        b"a\rb\rc\rd",
+    );
+    assert_eq!(out.stdout, b"4\n");
+}
+
+#[test]
+fn test_summary_json() {
+    let out = cw(
+        &["--summary-json", "-l", "--files-from", "-"],
+        b"Cargo.toml\nREADME.md\n",
+    );
+    assert!(out.status.success());
+    let lines = cw(&["-l", "--total=only", "Cargo.toml", "README.md"], b"");
+    let lines = String::from_utf8(lines.stdout).unwrap();
+    assert_eq!(
+        String::from_utf8(out.stdout).unwrap(),
+        format!("{{\"lines\":{},\"files\":2}}\n", lines.trim())
+    );
+
+    let out = cw(&["--summary-json", "-w"], b"one two\n");
+    assert_eq!(out.stdout, b"{\"words\":2,\"files\":1}\n");
+}
+
+#[test]
+fn test_by_extension() {
+    let rs = cw(&["-l", "-r", "--total=only", "src"], b"");
+    let rs = String::from_utf8(rs.stdout).unwrap();
+
+    let out = cw(
+        &[
+            "-l",
+            "--by-extension",
+            "-r",
+            "src",
+            "Cargo.toml",
+            "LICENSE.txt",
+        ],
+        b"",
+    );
+    assert!(out.status.success());
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    let rows: Vec<Vec<&str>> = stdout
+        .lines()
+        .map(|line| line.split_whitespace().collect())
+        .collect();
+
+    let labels: Vec<&str> = rows.iter().map(|row| row[1]).collect();
+    assert_eq!(labels, ["rs", "toml", "txt", "total"]);
+    assert_eq!(rows[0][0], rs.trim());
+
+    let bare = std::env::temp_dir().join(format!("cw-by-extension-{}", std::process::id()));
+    std::fs::write(&bare, "a\n").unwrap();
+    let out = cw(&["-l", "--by-extension", bare.to_str().unwrap()], b"");
+    assert_eq!(out.stdout, b"       1 (none)\n");
+    std::fs::remove_file(&bare).unwrap();
+}
+
+#[test]
+fn test_follow() {
+    use std::io::{BufRead, BufReader};
+
+    let path = std::env::temp_dir().join(format!("cw-follow-{}.log", std::process::id()));
+    std::fs::write(&path, "one two\nthree").unwrap();
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_cw"))
+        .args(["--follow", "-lw", path.to_str().unwrap()])
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("spawn cw");
+    let mut lines = BufReader::new(child.stdout.take().unwrap()).lines();
+
+    // The unfinished last line waits to be completed
+    let first = lines.next().unwrap().unwrap();
+    assert!(first.starts_with("       1       2 "), "{}", first);
+
+    let mut file = std::fs::OpenOptions::new()
+        .append(true)
+        .open(&path)
+        .unwrap();
+    file.write_all(b" four\nfive\n").unwrap();
+    let second = lines.next().unwrap().unwrap();
+    assert!(second.starts_with("       3       5 "), "{}", second);
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn test_interrupt() {
+    let input = b"one two\nthree\n";
+
+    // Strategies reading by the buffer or by the line, and stdin split between
+    // threads, all stop mid-read
+    for args in &[&["-lw"][..], &["-m", "-w"], &["-l", "--threads", "2"]] {
+        let expected = cw(args, input);
+
+        let mut child = Command::new(env!("CARGO_BIN_EXE_cw"))
+            .args(*args)
+            .stdin(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .expect("spawn cw");
+
+        // Stdin stays open, so cw is still waiting for more when interrupted
+        let mut stdin = child.stdin.take().unwrap();
+        stdin.write_all(input).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(500));
+        unsafe {
+            libc::kill(child.id() as libc::pid_t, libc::SIGINT);
+        }
+
+        let out = child.wait_with_output().unwrap();
+        assert_eq!(out.status.code(), Some(130), "{:?}", args);
+        assert_eq!(
+            String::from_utf8_lossy(&out.stderr),
+            format!(
+                "cw: interrupted\n{}",
+                String::from_utf8_lossy(&expected.stdout)
+            ),
+            "{:?}",
+            args
+        );
+        drop(stdin);
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn test_interrupt_files() {
+    let dir = temp_tree("interrupt_files", &[("a", "one two\nthree\n")]);
+    let a = dir.join("a");
+
+    // Files finished before Ctrl-C are printed and totalled, including rows
+    // held back to align columns, while the one cut short is left out, whether
+    // it is read as a file or as stdin
+    for (threads, stdin) in &[
+        ("1", "/dev/stdin"),
+        ("2", "/dev/stdin"),
+        ("1", "-"),
+        ("2", "-"),
+    ] {
+        let args = ["-l", "--threads", threads];
+        let mut child = Command::new(env!("CARGO_BIN_EXE_cw"))
+            .args(args)
+            .arg(&a)
+            .arg(stdin)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .expect("spawn cw");
+
+        let input = child.stdin.take().unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(500));
+        unsafe {
+            libc::kill(child.id() as libc::pid_t, libc::SIGINT);
+        }
+
+        let out = child.wait_with_output().unwrap();
+        assert_eq!(out.status.code(), Some(130), "{:?}", (threads, stdin));
+        assert_eq!(
+            String::from_utf8_lossy(&out.stdout)
+                .split_whitespace()
+                .collect::<Vec<_>>(),
+            ["2", &a.to_string_lossy()],
+            "{:?}",
+            (threads, stdin)
+        );
+        assert_eq!(
+            String::from_utf8_lossy(&out.stderr),
+            "cw: interrupted\n       2 total\n",
+            "{:?}",
+            (threads, stdin)
+        );
+        drop(input);
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn test_siginfo_signal() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_cw"))
+        .args(["-l", "--siginfo-signal", "sigusr2"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("spawn cw");
+
+    let mut stdin = child.stdin.take().unwrap();
+    stdin.write_all(b"one\ntwo\n").unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(500));
+    unsafe {
+        libc::kill(child.id() as libc::pid_t, libc::SIGUSR2);
+    }
+
+    // Progress is printed after the next read
+    std::thread::sleep(std::time::Duration::from_millis(100));
+    stdin.write_all(b"three\n").unwrap();
+    drop(stdin);
+
+    let out = child.wait_with_output().unwrap();
+    assert!(out.status.success());
+    assert_eq!(out.stdout, b"3\n");
+    assert_eq!(out.stderr, b"3\n");
+
+    let out = cw(&["--siginfo-signal", "KILL"], b"");
+    assert!(!out.status.success());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("unsupported signal KILL"));
+}